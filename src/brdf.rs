@@ -0,0 +1,181 @@
+use std::f64::consts::PI;
+
+use crate::linear::*;
+use crate::mat::{Color, Pbr};
+use crate::path::cosine_sample;
+use crate::rng::Rng;
+
+/// How much light dielectrics reflect head-on: about 4%, for an index of refraction of 1.5.
+const DIELECTRIC_F0: f64 = 0.04;
+
+/// Roughness is squared to get ggx's alpha, which can't be zero or the lobe is infinitely thin.
+const MIN_ALPHA: f64 = 1e-3;
+
+/// Shading for `Pbr` materials: a GGX (Trowbridge-Reitz) specular lobe with Smith masking and
+/// Schlick's Fresnel approximation, over a Lambertian diffuse lobe that only gets the light
+/// the specular lobe didn't reflect. All directions point away from the surface and are unit
+/// length.
+impl Pbr {
+    /// How much light is reflected straight back, per color channel.
+    pub fn f0(&self) -> Color {
+        Color::new(DIELECTRIC_F0, DIELECTRIC_F0, DIELECTRIC_F0).lerp(self.metallic, &self.base_color)
+    }
+
+    fn alpha(&self) -> f64 {
+        (self.roughness * self.roughness).max(MIN_ALPHA)
+    }
+
+    /// The BRDF times the cosine of the light's angle, for light arriving from `l` and leaving
+    /// towards `v`.
+    pub fn evaluate(&self, n: &Vec3, v: &Vec3, l: &Vec3) -> Color {
+        let (nl, nv) = (n * l, n * v);
+        if nl <= 0. || nv <= 0. {
+            return Color::black();
+        }
+        let h = (v + l).normalize();
+        let a2 = self.alpha() * self.alpha();
+        let fresnel = schlick(&self.f0(), v * &h);
+        let d = ggx(n * &h, a2);
+        let g = smith_g1(nv, a2) * smith_g1(nl, a2);
+        let specular = fresnel.clone().scale(d * g / (4. * nl * nv));
+        let diffuse = (&Color::white() - &fresnel)
+            .multiply(&self.base_color)
+            .scale((1. - self.metallic) / PI);
+        (&specular + &diffuse).scale(nl)
+    }
+
+    /// Light mirrored straight back along the reflection of `v`, for tracers that can only
+    /// follow one reflected ray. Rough surfaces blur their reflections away, so they get less.
+    pub fn mirror_reflectance(&self, n: &Vec3, v: &Vec3) -> Color {
+        let smoothness = 1. - self.roughness;
+        schlick(&self.f0(), (n * v).max(0.)).scale(smoothness * smoothness)
+    }
+
+    /// Picks a direction for the light to have come from, returning it along with the BRDF
+    /// times the cosine, divided by the chance of picking that direction.
+    pub fn sample(&self, n: &Vec3, v: &Vec3, rng: &mut Rng) -> Option<(Vec3, Color)> {
+        let specular_chance = self.specular_chance(n, v);
+        let l = if rng.next_f64() < specular_chance {
+            let h = self.sample_half_vector(n, rng);
+            v.clone().scale(-1.).add(2. * (v * &h), &h)
+        } else {
+            cosine_sample(n, rng)
+        };
+        if n * &l <= 0. {
+            return None;
+        }
+        let pdf = specular_chance * self.specular_pdf(n, v, &l) + (1. - specular_chance) * (n * &l) / PI;
+        if pdf <= 0. {
+            return None;
+        }
+        let weight = self.evaluate(n, v, &l).scale(1. / pdf);
+        Some((l, weight))
+    }
+
+    /// How often to sample the specular lobe rather than the diffuse one, by roughly how much
+    /// each of them reflects.
+    fn specular_chance(&self, n: &Vec3, v: &Vec3) -> f64 {
+        let average = |c: &Color| {
+            let (r, g, b): (f64, f64, f64) = c.into();
+            (r + g + b) / 3.
+        };
+        let fresnel = average(&schlick(&self.f0(), (n * v).max(0.)));
+        let diffuse = (1. - fresnel) * (1. - self.metallic) * average(&self.base_color);
+        if fresnel + diffuse <= 0. {
+            return 0.5;
+        }
+        (fresnel / (fresnel + diffuse)).clamp(0.1, 0.9)
+    }
+
+    /// A microfacet normal, picked in proportion to how many facets face that way.
+    fn sample_half_vector(&self, n: &Vec3, rng: &mut Rng) -> Vec3 {
+        let a2 = self.alpha() * self.alpha();
+        let u = rng.next_f64();
+        let phi = 2. * PI * rng.next_f64();
+        let cos_theta = ((1. - u) / (1. + (a2 - 1.) * u)).sqrt();
+        let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+        Basis::around(n).project(&Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta))
+    }
+
+    fn specular_pdf(&self, n: &Vec3, v: &Vec3, l: &Vec3) -> f64 {
+        let h = (v + l).normalize();
+        let (nh, vh) = (n * &h, v * &h);
+        if nh <= 0. || vh <= 0. {
+            return 0.;
+        }
+        let a2 = self.alpha() * self.alpha();
+        ggx(nh, a2) * nh / (4. * vh)
+    }
+}
+
+/// The GGX distribution of microfacet normals.
+fn ggx(nh: f64, a2: f64) -> f64 {
+    let d = nh * nh * (a2 - 1.) + 1.;
+    a2 / (PI * d * d)
+}
+
+/// The fraction of microfacets visible from a direction, by Smith's approximation.
+fn smith_g1(cos: f64, a2: f64) -> f64 {
+    2. * cos / (cos + (a2 + (1. - a2) * cos * cos).sqrt())
+}
+
+/// Reflectance rises to 1 at grazing angles.
+fn schlick(f0: &Color, cos: f64) -> Color {
+    let t = (1. - cos.clamp(0., 1.)).powi(5);
+    f0.clone().lerp(t, &Color::white())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::brdf::*;
+    use crate::mat::Material;
+
+    #[test]
+    fn brdf_is_reciprocal_and_conserves_energy() {
+        let n = Vec3::up();
+        let v = Vec3::new(0.3, 1., 0.2).normalize();
+        let l = Vec3::new(-0.6, 0.5, 0.1).normalize();
+        let mut rng = Rng::new(5, 0);
+        for pbr in &[
+            Pbr::new(Color::white(), 0., 0.5),
+            Pbr::new(Color::white(), 1., 0.3),
+            Pbr::new(Color::new(0.9, 0.6, 0.2), 0.5, 0.8),
+        ] {
+            let forward: (f64, f64, f64) = (&pbr.evaluate(&n, &v, &l).scale(1. / (&n * &l))).into();
+            let backward: (f64, f64, f64) = (&pbr.evaluate(&n, &l, &v).scale(1. / (&n * &v))).into();
+            assert!((forward.0 - backward.0).abs() < 1e-9);
+
+            // the average sample weight is the fraction of light reflected, which can't be
+            // more than all of it. a white metal should reflect nearly all of it.
+            let count = 20_000;
+            let total = (0..count)
+                .filter_map(|_| pbr.sample(&n, &v, &mut rng))
+                .fold(Color::black(), |total, (_, weight)| total.add(1. / count as f64, &weight));
+            let (r, _, _): (f64, f64, f64) = (&total).into();
+            assert!(r < 1.02, "{:?} reflects {}", pbr, r);
+            if pbr.metallic == 1. {
+                assert!(r > 0.85, "{:?} reflects {}", pbr, r);
+            }
+        }
+    }
+
+    #[test]
+    fn phong_conversion() {
+        let mut plastic = Material::new();
+        plastic.diffuse = Color::new(1., 0., 0.);
+        plastic.phong = 100.;
+        let pbr = Pbr::from_phong(&plastic);
+        assert_eq!(pbr.metallic, 0.);
+        assert_eq!(pbr.base_color, Color::new(1., 0., 0.));
+        assert!(pbr.roughness < 0.5);
+
+        let mut mirror = Material::new();
+        mirror.reflectivity = 1.;
+        mirror.specular = Color::new(1., 0.8, 0.4);
+        mirror.phong = 1.;
+        let pbr = Pbr::from_phong(&mirror);
+        assert_eq!(pbr.metallic, 1.);
+        assert_eq!(pbr.base_color, Color::new(1., 0.8, 0.4));
+        assert!(pbr.roughness > 0.8);
+    }
+}
@@ -0,0 +1,81 @@
+use crate::mat::Color;
+
+/// Running sums of every sample taken for each pixel, so the image can keep improving over
+/// many passes. Colors aren't clamped until they're shown, so bright samples still pull the
+/// average up the way they should.
+pub struct AccumulationBuffer {
+    width: usize,
+    height: usize,
+    sums: Vec<(f64, f64, f64)>,
+    counts: Vec<u32>,
+}
+
+impl AccumulationBuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            sums: vec![(0., 0., 0.); width * height],
+            counts: vec![0; width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Adds a sample to the pixel. Misses count as black.
+    pub fn add(&mut self, index: usize, color: Option<Color>) {
+        let (r, g, b) = color.as_ref().map(|c| c.into()).unwrap_or((0., 0., 0.));
+        let sum = &mut self.sums[index];
+        *sum = (sum.0 + r, sum.1 + g, sum.2 + b);
+        self.counts[index] += 1;
+    }
+
+    /// Throws away the pixel's samples and starts over with this one.
+    pub fn set(&mut self, index: usize, color: Option<Color>) {
+        self.sums[index] = (0., 0., 0.);
+        self.counts[index] = 0;
+        self.add(index, color);
+    }
+
+    /// The average of the pixel's samples, or nothing if it doesn't have any yet.
+    pub fn average(&self, index: usize) -> Option<Color> {
+        let count = self.counts[index];
+        if count == 0 {
+            return None;
+        }
+        let (r, g, b) = self.sums[index];
+        Some(Color::new(r, g, b).scale(1. / count as f64))
+    }
+
+    /// Forgets every sample, for when the camera or scene changes.
+    pub fn reset(&mut self) {
+        self.sums.iter_mut().for_each(|sum| *sum = (0., 0., 0.));
+        self.counts.iter_mut().for_each(|count| *count = 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::buffer::*;
+
+    #[test]
+    fn running_average() {
+        let mut buffer = AccumulationBuffer::new(2, 1);
+        assert_eq!(buffer.average(0), None);
+        buffer.add(0, Some(Color::new(3., 0., 1.)));
+        buffer.add(0, None);
+        assert_eq!(buffer.average(0), Some(Color::new(1.5, 0., 0.5)));
+        assert_eq!(buffer.average(1), None);
+
+        buffer.set(0, Some(Color::white()));
+        assert_eq!(buffer.average(0), Some(Color::white()));
+        buffer.reset();
+        assert_eq!(buffer.average(0), None);
+    }
+}
@@ -0,0 +1,286 @@
+use crate::linear::*;
+use crate::mat::Material;
+use crate::sdf::SDF;
+
+/// A tube swept along a chain of cubic Bezier segments, with a radius that varies linearly
+/// along each segment. The shape is the union of spheres centered along the curve, so the
+/// distance to it is the smallest distance to any of those spheres, which we find by
+/// subdividing the curve until the answer is within `epsilon / 2`.
+#[derive(Clone)]
+pub struct CurveTube {
+    segments: Vec<Segment>,
+    gradient: Option<(Material, Material)>,
+    epsilon: f64,
+}
+
+/// Part of the curve, along with where it sits in the parameter and radius of the whole.
+#[derive(Clone)]
+struct Segment {
+    points: [Vec3; 4],
+    t: (f64, f64),
+    radius: (f64, f64),
+}
+
+struct Nearest {
+    distance: f64,
+    point: Vec3,
+    t: f64,
+}
+
+/// How many times a segment can be halved while looking for the nearest point.
+const MAX_SUBDIVISIONS: usize = 24;
+
+impl CurveTube {
+    pub fn quadratic(a: Vec3, b: Vec3, c: Vec3, radius: (f64, f64)) -> Self {
+        // every quadratic is also a cubic.
+        let b1 = a.clone().lerp(2. / 3., &b);
+        let b2 = c.clone().lerp(2. / 3., &b);
+        Self::cubic(a, b1, b2, c, radius)
+    }
+
+    pub fn cubic(a: Vec3, b: Vec3, c: Vec3, d: Vec3, radius: (f64, f64)) -> Self {
+        Self::new(vec![Segment { points: [a, b, c, d], t: (0., 1.), radius }])
+    }
+
+    /// A smooth curve through all the points, with a radius for each of them.
+    pub fn catmull_rom(points: Vec<Vec3>, radii: Vec<f64>) -> Result<Self, String> {
+        if points.len() < 2 {
+            return Err(format!("spline needs at least 2 points, got {}", points.len()));
+        }
+        if radii.len() != points.len() {
+            return Err(format!("spline has {} points, but {} radii", points.len(), radii.len()));
+        }
+        let n = points.len() - 1;
+        let point = |i: isize| &points[i.max(0).min(n as isize) as usize];
+        let segments = (0..n).map(|i| {
+            let i = i as isize;
+            let (p0, p1, p2, p3) = (point(i - 1), point(i), point(i + 1), point(i + 2));
+            // the tangent at each point is half the vector between its neighbors, and a
+            // bezier's handles are a third of the tangent away.
+            Segment {
+                points: [
+                    p1.clone(),
+                    p1.clone().add(1. / 6., &(p2 - p0)),
+                    p2.clone().add(-1. / 6., &(p3 - p1)),
+                    p2.clone(),
+                ],
+                t: (i as f64 / n as f64, (i + 1) as f64 / n as f64),
+                radius: (radii[i as usize], radii[i as usize + 1]),
+            }
+        }).collect();
+        Ok(Self::new(segments))
+    }
+
+    fn new(segments: Vec<Segment>) -> Self {
+        let radius = segments.iter()
+            .map(|s| s.radius.0.max(s.radius.1))
+            .fold(0., f64::max);
+        let size = Bounds::around(segments.iter().flat_map(|s| s.points.iter())).size().norm();
+        let epsilon = radius.max(size / 100.) / 1_000.0;
+        Self { segments, gradient: None, epsilon }
+    }
+
+    /// Colors the tube from `start` to `end` along the curve.
+    pub fn gradient(mut self, start: Material, end: Material) -> Self {
+        self.gradient = Some((start, end));
+        self
+    }
+
+    /// How far along the curve (from 0 to 1) the closest point to this one is.
+    pub fn parameter(&self, point: &Vec3) -> f64 {
+        self.nearest(point).t
+    }
+
+    fn nearest(&self, point: &Vec3) -> Nearest {
+        let tolerance = self.epsilon / 2.;
+        let mut nearest = Nearest { distance: f64::INFINITY, point: point.clone(), t: 0. };
+        // the lowest bound among pieces we gave up subdividing.
+        let mut floor = f64::INFINITY;
+        let mut stack: Vec<(Segment, usize)> = self.segments.iter().map(|s| (s.clone(), 0)).collect();
+        while let Some((segment, depth)) = stack.pop() {
+            let lower_bound = segment.lower_bound(point);
+            if lower_bound >= nearest.distance - tolerance {
+                continue;
+            }
+            let (s, center) = segment.closest_on_chord(point);
+            let curve_point = segment.at(s);
+            let distance = curve_point.dist(point) - segment.radius_at(s);
+            if distance < nearest.distance {
+                nearest = Nearest {
+                    distance,
+                    point: curve_point,
+                    t: segment.t.0 + s * (segment.t.1 - segment.t.0),
+                };
+            }
+            if nearest.distance - lower_bound <= tolerance {
+                continue;
+            }
+            if depth >= MAX_SUBDIVISIONS {
+                floor = floor.min(lower_bound);
+                continue;
+            }
+            let (first, second) = segment.split();
+            // look at the closer half first (it's popped last), so we can skip more.
+            if center < 0.5 {
+                stack.push((second, depth + 1));
+                stack.push((first, depth + 1));
+            } else {
+                stack.push((first, depth + 1));
+                stack.push((second, depth + 1));
+            }
+        }
+        // every piece we skipped could be up to `tolerance` closer than what we found.
+        nearest.distance = (nearest.distance - tolerance).min(floor);
+        nearest
+    }
+}
+
+impl Segment {
+    fn at(&self, s: f64) -> Vec3 {
+        let [a, b, c, d] = &self.points;
+        let u = 1. - s;
+        a.clone()
+            .scale(u * u * u)
+            .add(3. * u * u * s, b)
+            .add(3. * u * s * s, c)
+            .add(s * s * s, d)
+    }
+
+    fn radius_at(&self, s: f64) -> f64 {
+        self.radius.0 + (self.radius.1 - self.radius.0) * s
+    }
+
+    /// Splits in half with de Casteljau's algorithm.
+    fn split(&self) -> (Segment, Segment) {
+        let [a, b, c, d] = &self.points;
+        let ab = a.clone().lerp(0.5, b);
+        let bc = b.clone().lerp(0.5, c);
+        let cd = c.clone().lerp(0.5, d);
+        let abc = ab.clone().lerp(0.5, &bc);
+        let bcd = bc.lerp(0.5, &cd);
+        let mid = abc.clone().lerp(0.5, &bcd);
+        let t = (self.t.0 + self.t.1) / 2.;
+        let radius = (self.radius.0 + self.radius.1) / 2.;
+        (
+            Segment { points: [a.clone(), ab, abc, mid.clone()], t: (self.t.0, t), radius: (self.radius.0, radius) },
+            Segment { points: [mid, bcd, cd, d.clone()], t: (t, self.t.1), radius: (radius, self.radius.1) },
+        )
+    }
+
+    /// Where the point projects onto the chord from the first to the last control point, as
+    /// a fraction of the way along it, clamped and unclamped.
+    fn closest_on_chord(&self, point: &Vec3) -> (f64, f64) {
+        let [a, _, _, d] = &self.points;
+        let chord = d - a;
+        if chord.norm2() == 0. {
+            return (0.5, 0.5);
+        }
+        let s = &(point - a) * &chord / chord.norm2();
+        (s.clamp(0., 1.), s)
+    }
+
+    fn chord_distance(&self, point: &Vec3) -> f64 {
+        let [a, _, _, d] = &self.points;
+        let (s, _) = self.closest_on_chord(point);
+        a.clone().lerp(s, d).dist(point)
+    }
+
+    /// No point on this piece of the tube is closer than this. The curve stays inside the
+    /// hull of its control points, which is no farther from the chord than the inner ones.
+    fn lower_bound(&self, point: &Vec3) -> f64 {
+        let bulge = self.chord_distance(&self.points[1]).max(self.chord_distance(&self.points[2]));
+        self.chord_distance(point) - bulge - self.radius.0.max(self.radius.1)
+    }
+}
+
+impl SDF for CurveTube {
+    fn distance(&self, point: &Vec3) -> f64 {
+        self.nearest(point).distance
+    }
+
+    fn normal(&self, point: &Vec3) -> Vec3 {
+        // the surface is made of spheres, so it faces away from the center of the nearest one.
+        let nearest = self.nearest(point);
+        let normal = point - &nearest.point;
+        if normal.norm2() == 0. {
+            return Vec3::up();
+        }
+        normal.normalize()
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.epsilon
+    }
+
+    fn material(&self, point: &Vec3) -> Option<Material> {
+        let (start, end) = self.gradient.as_ref()?;
+        Some(start.clone().lerp(self.parameter(point), end))
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        let radius = self.segments.iter()
+            .map(|s| s.radius.0.max(s.radius.1))
+            .fold(0., f64::max);
+        let bounds = Bounds::around(self.segments.iter().flat_map(|s| s.points.iter()));
+        Some(bounds.expand(&Vec3::new(radius, radius, radius)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::curve::*;
+    use crate::mat::Color;
+
+    #[test]
+    fn curve_distances() {
+        // a quarter circle-ish arc from +x to +y.
+        let arc = CurveTube::quadratic(
+            Vec3::new(1., 0., 0.),
+            Vec3::new(1., 1., 0.),
+            Vec3::new(0., 1., 0.),
+            (0.1, 0.1),
+        );
+        // it bulges away from the origin, so the ends are closest.
+        assert!((arc.distance(&Vec3::zero()) - 0.9).abs() < arc.epsilon());
+        assert!((arc.parameter(&Vec3::new(3., 3., 0.)) - 0.5).abs() < 0.001);
+        assert!((arc.distance(&Vec3::new(1., -2., 0.)) - 1.9).abs() < arc.epsilon());
+
+        // brute force the nearest sphere along the curve to check we never overshoot.
+        let cable = CurveTube::catmull_rom(
+            vec![Vec3::zero(), Vec3::new(1., 1., 0.), Vec3::new(2., 0., 1.), Vec3::new(3., 1., 1.)],
+            vec![0.1, 0.3, 0.2, 0.05],
+        ).unwrap();
+        for point in &[Vec3::new(1., 0., 0.), Vec3::new(2., 2., 2.), Vec3::new(-1., 0.5, 0.), Vec3::new(1.5, 0.5, 0.5)] {
+            let exact = cable.segments.iter()
+                .flat_map(|s| (0..=1000).map(move |i| {
+                    let s2 = i as f64 / 1000.;
+                    s.at(s2).dist(point) - s.radius_at(s2)
+                }))
+                .fold(f64::INFINITY, f64::min);
+            let d = cable.distance(point);
+            assert!(d <= exact && d > exact - 0.01, "{} vs {} at {}", d, exact, point);
+        }
+        assert!((cable.distance(&Vec3::new(1., 1.5, 0.)) - 0.2).abs() < 0.001);
+        assert!(CurveTube::catmull_rom(vec![Vec3::zero()], vec![1.]).is_err());
+    }
+
+    #[test]
+    fn curve_gradient() {
+        let material = |color: Color| {
+            let mut m = Material::new();
+            m.diffuse = color;
+            m
+        };
+        let stem = CurveTube::cubic(
+            Vec3::zero(),
+            Vec3::new(0., 1., 0.),
+            Vec3::new(0., 2., 0.),
+            Vec3::new(0., 3., 0.),
+            (0.1, 0.1),
+        ).gradient(material(Color::black()), material(Color::white()));
+        let color = |y: f64| stem.material(&Vec3::new(0.5, y, 0.)).unwrap().diffuse.to_string();
+        assert_eq!(color(-1.), "#000000");
+        assert!((stem.parameter(&Vec3::new(0.5, 1.5, 0.)) - 0.5).abs() < 0.001);
+        assert_eq!(color(4.), "#ffffff");
+    }
+}
@@ -0,0 +1,361 @@
+use crate::linear::*;
+use crate::mat::Material;
+use crate::sdf::SDF;
+
+/// A distance-estimated fractal, which can also say how a point's orbit behaved.
+pub trait Fractal: SDF {
+    /// From 0, where the orbit stays close to the origin (deep in the set), up to 1, where it
+    /// wanders off or escapes quickly.
+    fn orbit_trap(&self, point: &Vec3) -> f64;
+}
+
+/// Colors a fractal by blending from `near` to `far` along its orbit trap.
+#[derive(Clone)]
+pub struct TrapPalette {
+    near: Material,
+    far: Material,
+}
+
+/// The classic 3d Mandelbrot analogue, raising points to `power` in spherical coordinates.
+/// Fits inside a sphere of radius 1.5.
+#[derive(Clone)]
+pub struct Mandelbulb {
+    power: f64,
+    iterations: usize,
+    palette: Option<TrapPalette>,
+}
+
+/// A cube of size 2 centered on the origin, with the middle of each face and the center
+/// recursively carved out.
+#[derive(Clone)]
+pub struct MengerSponge {
+    iterations: usize,
+    palette: Option<TrapPalette>,
+}
+
+/// A tetrahedron with vertices at (1, 1, 1), (-1, -1, 1), (1, -1, -1) and (-1, 1, -1),
+/// recursively replaced by four half-size copies at its corners.
+#[derive(Clone)]
+pub struct SierpinskiTetrahedron {
+    iterations: usize,
+    palette: Option<TrapPalette>,
+}
+
+/// The w = 0 slice of a quaternion Julia set, iterating z² + c. Fits inside a sphere of radius
+/// 2 as long as |c| <= 2.
+#[derive(Clone)]
+pub struct JuliaSet {
+    c: [f64; 4],
+    iterations: usize,
+    palette: Option<TrapPalette>,
+}
+
+const FRACTAL_EPSILON: f64 = 0.0005;
+
+impl TrapPalette {
+    pub fn new(near: Material, far: Material) -> Self {
+        Self { near, far }
+    }
+
+    fn material(palette: &Option<TrapPalette>, trap: f64) -> Option<Material> {
+        let palette = palette.as_ref()?;
+        Some(palette.near.clone().lerp(trap.clamp(0., 1.), &palette.far))
+    }
+}
+
+impl Mandelbulb {
+    pub fn new(power: f64, iterations: usize) -> Self {
+        Self { power, iterations, palette: None }
+    }
+
+    pub fn palette(mut self, palette: TrapPalette) -> Self {
+        self.palette = Some(palette);
+        self
+    }
+
+    /// The distance estimate and the closest the orbit came to the origin.
+    fn orbit(&self, point: &Vec3) -> (f64, f64) {
+        let mut z = point.clone();
+        let mut dr = 1.;
+        let mut r = z.norm();
+        let mut trap = r;
+        for _ in 0..self.iterations {
+            if r > 2. {
+                break;
+            }
+            let theta = if r > 0. { (z.z / r).acos() * self.power } else { 0. };
+            let phi = z.y.atan2(z.x) * self.power;
+            dr = r.powf(self.power - 1.) * self.power * dr + 1.;
+            z = Vec3::new(
+                theta.sin() * phi.cos(),
+                theta.sin() * phi.sin(),
+                theta.cos(),
+            ).scale(r.powf(self.power)).add(1., point);
+            r = z.norm();
+            trap = trap.min(r);
+        }
+        let r = r.max(1e-12);
+        (0.5 * r.ln() * r / dr, trap)
+    }
+}
+
+impl MengerSponge {
+    pub fn new(iterations: usize) -> Self {
+        Self { iterations, palette: None }
+    }
+
+    pub fn palette(mut self, palette: TrapPalette) -> Self {
+        self.palette = Some(palette);
+        self
+    }
+
+    /// The distance and how near the point is to a hole at the level that carved it.
+    fn orbit(&self, point: &Vec3) -> (f64, f64) {
+        // https://iquilezles.org/www/articles/menger/menger.htm
+        let q = Vec3::new(point.x.abs() - 1., point.y.abs() - 1., point.z.abs() - 1.);
+        let mut d = Vec3::new(q.x.max(0.), q.y.max(0.), q.z.max(0.)).norm()
+            + q.x.max(q.y).max(q.z).min(0.);
+        let mut trap: f64 = 1.;
+        let mut s = 1.;
+        for _ in 0..self.iterations {
+            let fold = |x: f64| {
+                let a = (x * s).rem_euclid(2.) - 1.;
+                (1. - 3. * a.abs()).abs()
+            };
+            let r = Vec3::new(fold(point.x), fold(point.y), fold(point.z));
+            s *= 3.;
+            let da = r.x.max(r.y);
+            let db = r.y.max(r.z);
+            let dc = r.z.max(r.x);
+            let c = (da.min(db).min(dc) - 1.) / s;
+            if c > d {
+                d = c;
+                trap = trap.min(0.2 * da * db * dc);
+            }
+        }
+        (d, trap)
+    }
+}
+
+impl SierpinskiTetrahedron {
+    pub fn new(iterations: usize) -> Self {
+        Self { iterations, palette: None }
+    }
+
+    pub fn palette(mut self, palette: TrapPalette) -> Self {
+        self.palette = Some(palette);
+        self
+    }
+
+    /// The distance and how far the orbit strayed from the origin, relative to where it
+    /// would have to go to leave the tetrahedron.
+    fn orbit(&self, point: &Vec3) -> (f64, f64) {
+        let mut z = point.clone();
+        let mut scale = 1.;
+        let mut trap: f64 = 0.;
+        for _ in 0..self.iterations {
+            // fold the point into the copy at (1, 1, 1) by reflecting across the planes
+            // between it and the other three.
+            if z.x + z.y < 0. {
+                z = Vec3::new(-z.y, -z.x, z.z);
+            }
+            if z.x + z.z < 0. {
+                z = Vec3::new(-z.z, z.y, -z.x);
+            }
+            if z.y + z.z < 0. {
+                z = Vec3::new(z.x, -z.z, -z.y);
+            }
+            z = z.scale(2.).add(-1., &Vec3::new(1., 1., 1.));
+            scale *= 2.;
+            trap = trap.max(z.norm() / 3f64.sqrt() - 1.);
+        }
+        let tetrahedron = (-z.x - z.y - z.z).max(z.x + z.y - z.z)
+            .max(-z.x + z.y + z.z)
+            .max(z.x - z.y + z.z);
+        ((tetrahedron - 1.) / 3f64.sqrt() / scale, trap)
+    }
+}
+
+impl JuliaSet {
+    /// `c` is the quaternion (w, x, y, z) added on each iteration.
+    pub fn new(c: [f64; 4], iterations: usize) -> Self {
+        Self { c, iterations, palette: None }
+    }
+
+    pub fn palette(mut self, palette: TrapPalette) -> Self {
+        self.palette = Some(palette);
+        self
+    }
+
+    /// The distance estimate and the closest the orbit came to the origin.
+    fn orbit(&self, point: &Vec3) -> (f64, f64) {
+        // https://iquilezles.org/www/articles/juliasets3d/juliasets3d.htm
+        // space is the slice through the quaternions where the last imaginary part is 0.
+        let mut z = [point.x, point.y, point.z, 0.];
+        let mut m2 = point.norm2();
+        // |dz/dz0|², which doubles along with |z| every iteration.
+        let mut dz2 = 1.;
+        let mut trap = m2;
+        for _ in 0..self.iterations {
+            dz2 *= 4. * m2;
+            z = [
+                z[0] * z[0] - z[1] * z[1] - z[2] * z[2] - z[3] * z[3] + self.c[0],
+                2. * z[0] * z[1] + self.c[1],
+                2. * z[0] * z[2] + self.c[2],
+                2. * z[0] * z[3] + self.c[3],
+            ];
+            m2 = z.iter().map(|v| v * v).sum();
+            trap = trap.min(m2);
+            if m2 > 256. {
+                break;
+            }
+        }
+        let (m2, dz2) = (m2.max(1e-12), dz2.max(1e-12));
+        (0.25 * m2.ln() * (m2 / dz2).sqrt(), trap.sqrt() / 2.)
+    }
+}
+
+impl SDF for Mandelbulb {
+    fn distance(&self, point: &Vec3) -> f64 {
+        self.orbit(point).0
+    }
+
+    fn epsilon(&self) -> f64 {
+        FRACTAL_EPSILON
+    }
+
+    fn material(&self, point: &Vec3) -> Option<Material> {
+        TrapPalette::material(&self.palette, self.orbit_trap(point))
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        Some(Bounds::centered(Vec3::new(1.5, 1.5, 1.5)))
+    }
+}
+
+impl Fractal for Mandelbulb {
+    fn orbit_trap(&self, point: &Vec3) -> f64 {
+        self.orbit(point).1.min(1.)
+    }
+}
+
+impl SDF for MengerSponge {
+    fn distance(&self, point: &Vec3) -> f64 {
+        self.orbit(point).0
+    }
+
+    fn epsilon(&self) -> f64 {
+        FRACTAL_EPSILON
+    }
+
+    fn material(&self, point: &Vec3) -> Option<Material> {
+        TrapPalette::material(&self.palette, self.orbit_trap(point))
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        Some(Bounds::centered(Vec3::new(1., 1., 1.)))
+    }
+}
+
+impl Fractal for MengerSponge {
+    fn orbit_trap(&self, point: &Vec3) -> f64 {
+        self.orbit(point).1.min(1.)
+    }
+}
+
+impl SDF for SierpinskiTetrahedron {
+    fn distance(&self, point: &Vec3) -> f64 {
+        self.orbit(point).0
+    }
+
+    fn epsilon(&self) -> f64 {
+        FRACTAL_EPSILON
+    }
+
+    fn material(&self, point: &Vec3) -> Option<Material> {
+        TrapPalette::material(&self.palette, self.orbit_trap(point))
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        Some(Bounds::centered(Vec3::new(1., 1., 1.)))
+    }
+}
+
+impl Fractal for SierpinskiTetrahedron {
+    fn orbit_trap(&self, point: &Vec3) -> f64 {
+        self.orbit(point).1.min(1.)
+    }
+}
+
+impl SDF for JuliaSet {
+    fn distance(&self, point: &Vec3) -> f64 {
+        self.orbit(point).0
+    }
+
+    fn epsilon(&self) -> f64 {
+        FRACTAL_EPSILON
+    }
+
+    fn material(&self, point: &Vec3) -> Option<Material> {
+        TrapPalette::material(&self.palette, self.orbit_trap(point))
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        Some(Bounds::centered(Vec3::new(2., 2., 2.)))
+    }
+}
+
+impl Fractal for JuliaSet {
+    fn orbit_trap(&self, point: &Vec3) -> f64 {
+        self.orbit(point).1.min(1.)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fractal::*;
+    use crate::mat::{Color, Material};
+
+    #[test]
+    fn fractal_distances() {
+        let bulb = Mandelbulb::new(8., 12);
+        assert!(bulb.distance(&Vec3::zero()) <= 0.);
+        assert!(bulb.distance(&Vec3::new(3., 0., 0.)) > 1.);
+        assert!(bulb.distance(&Vec3::new(3., 0., 0.)) < 2.);
+
+        let sponge = MengerSponge::new(3);
+        assert_eq!(sponge.distance(&Vec3::new(0., 0., 3.)).to_string(), 2.0.to_string());
+        // the center tunnels are carved out, but the corners aren't.
+        assert!(sponge.distance(&Vec3::new(0., 0., 0.9)) > 0.);
+        assert!(sponge.distance(&Vec3::new(0.97, 0.97, 0.97)) < 0.);
+
+        let tetrahedron = SierpinskiTetrahedron::new(6);
+        assert!(tetrahedron.distance(&Vec3::new(0.99, 0.99, 0.99)) < 0.01);
+        assert!(tetrahedron.distance(&Vec3::zero()) > 0.);
+        assert!(tetrahedron.distance(&Vec3::new(0., 0., 3.)) > 1.);
+
+        let julia = JuliaSet::new([-0.2, 0.8, 0., 0.], 11);
+        assert!(julia.distance(&Vec3::new(0., 0., 4.)) > 1.);
+        // a julia slice isn't symmetric around the origin like a ball is.
+        let (real, imaginary) = (julia.distance(&Vec3::new(0.8, 0., 0.)), julia.distance(&Vec3::new(0., 0.8, 0.)));
+        assert!((real - imaginary).abs() > 1e-3, "{} {}", real, imaginary);
+        for point in &[Vec3::zero(), Vec3::new(0.5, 0.5, 0.), Vec3::new(4., 0., 0.)] {
+            assert!(!julia.distance(point).is_nan());
+            assert!((0. ..=1.).contains(&julia.orbit_trap(point)));
+        }
+    }
+
+    #[test]
+    fn orbit_trap_colors() {
+        let material = |hex: &str| {
+            let mut m = Material::new();
+            m.diffuse = Color::from_hexstring(hex);
+            m
+        };
+        let bulb = Mandelbulb::new(8., 12);
+        assert!(bulb.material(&Vec3::zero()).is_none());
+        let bulb = bulb.palette(TrapPalette::new(material("#000000"), material("#ffffff")));
+        assert_eq!(bulb.material(&Vec3::zero()).unwrap().diffuse.to_string(), "#000000");
+        assert_eq!(bulb.material(&Vec3::new(3., 0., 0.)).unwrap().diffuse.to_string(), "#ffffff");
+    }
+}
@@ -0,0 +1,460 @@
+use std::convert::TryInto;
+
+use crate::linear::*;
+use crate::mat::{Color, Material, Pbr};
+use crate::sdf::SDF;
+
+/// How distances are blended between grid samples.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpolation {
+    /// Cheap, but the gradient jumps at cell boundaries, which shows up as faceted shading.
+    Trilinear,
+    /// Catmull-Rom through the surrounding 4x4x4 samples, which has a continuous gradient.
+    Tricubic,
+}
+
+impl Interpolation {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "trilinear" => Ok(Interpolation::Trilinear),
+            "tricubic" => Ok(Interpolation::Tricubic),
+            _ => Err(format!("unknown interpolation: {}", name)),
+        }
+    }
+}
+
+/// Distances sampled on a regular grid, with samples on the corners of the bounds. Useful for
+/// caching an expensive sdf, or importing a distance field from elsewhere.
+#[derive(Clone)]
+pub struct VoxelGrid {
+    bounds: Bounds,
+    resolution: (usize, usize, usize),
+    distances: Vec<f32>,
+    /// indices into `palette` for each sample, if the grid has materials.
+    material_ids: Option<Vec<u16>>,
+    palette: Vec<Material>,
+    interpolation: Interpolation,
+    epsilon: f64,
+}
+
+const MAGIC: &[u8; 4] = b"GVOX";
+const VERSION: u32 = 1;
+const NO_MATERIAL: u16 = u16::MAX;
+
+impl VoxelGrid {
+    /// Resolution is the number of samples along each axis, including both ends.
+    pub fn new(
+        bounds: Bounds,
+        resolution: (usize, usize, usize),
+        distances: Vec<f32>,
+        interpolation: Interpolation,
+    ) -> Result<Self, String> {
+        let (nx, ny, nz) = resolution;
+        if nx < 2 || ny < 2 || nz < 2 {
+            return Err(format!("grid needs at least 2 samples per axis, got {:?}", resolution));
+        }
+        if distances.len() != nx * ny * nz {
+            return Err(format!(
+                "{:?} grid needs {} samples, got {}", resolution, nx * ny * nz, distances.len(),
+            ));
+        }
+        let size = bounds.size();
+        let cell = (size.x / (nx - 1) as f64)
+            .min(size.y / (ny - 1) as f64)
+            .min(size.z / (nz - 1) as f64);
+        if cell.is_nan() || cell <= 0. {
+            return Err("grid bounds have no volume".to_string());
+        }
+        Ok(Self {
+            bounds,
+            resolution,
+            distances,
+            material_ids: None,
+            palette: vec![],
+            interpolation,
+            epsilon: cell / 100.,
+        })
+    }
+
+    /// Samples the sdf (and its materials, if it has any) at every grid point.
+    pub fn bake(
+        sdf: &dyn SDF,
+        bounds: Bounds,
+        resolution: (usize, usize, usize),
+        interpolation: Interpolation,
+    ) -> Result<Self, String> {
+        let (nx, ny, nz) = resolution;
+        let mut grid = Self::new(bounds, resolution, vec![0.; nx * ny * nz], interpolation)?;
+        let mut material_ids = vec![NO_MATERIAL; grid.distances.len()];
+        for z in 0..nz {
+            for y in 0..ny {
+                for x in 0..nx {
+                    let index = grid.index(x, y, z);
+                    let point = grid.sample_point(x, y, z);
+                    grid.distances[index] = sdf.distance(&point) as f32;
+                    if let Some(material) = sdf.material(&point) {
+                        material_ids[index] = grid.palette_id(material)?;
+                    }
+                }
+            }
+        }
+        if !grid.palette.is_empty() {
+            grid.material_ids = Some(material_ids);
+        }
+        Ok(grid)
+    }
+
+    pub fn with_materials(mut self, material_ids: Vec<u16>, palette: Vec<Material>) -> Result<Self, String> {
+        if material_ids.len() != self.distances.len() {
+            return Err(format!(
+                "grid has {} samples, but got {} material ids", self.distances.len(), material_ids.len(),
+            ));
+        }
+        if let Some(id) = material_ids.iter().find(|&&id| id != NO_MATERIAL && id as usize >= palette.len()) {
+            return Err(format!("material id {} is missing from the palette", id));
+        }
+        self.material_ids = Some(material_ids);
+        self.palette = palette;
+        Ok(self)
+    }
+
+    /// Whether the grid brings its own materials, from being baked from a shaded sdf.
+    pub fn has_materials(&self) -> bool {
+        self.material_ids.is_some()
+    }
+
+    /// Writes the grid in a little-endian binary format: the magic "GVOX", a u32 version, the
+    /// u32 resolution, the f64 min and max bounds, f32 distances with x varying fastest, a u32
+    /// palette size, and if it's non-zero, the palette followed by u16 material ids.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(&VERSION.to_le_bytes());
+        let (nx, ny, nz) = self.resolution;
+        for n in &[nx, ny, nz] {
+            bytes.extend(&(*n as u32).to_le_bytes());
+        }
+        for v in &[&self.bounds.min, &self.bounds.max] {
+            for c in &[v.x, v.y, v.z] {
+                bytes.extend(&c.to_le_bytes());
+            }
+        }
+        for d in &self.distances {
+            bytes.extend(&d.to_le_bytes());
+        }
+        bytes.extend(&(self.palette.len() as u32).to_le_bytes());
+        if let Some(material_ids) = &self.material_ids {
+            for m in &self.palette {
+                for value in material_values(m).iter() {
+                    bytes.extend(&value.to_le_bytes());
+                }
+            }
+            for id in material_ids {
+                bytes.extend(&id.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8], interpolation: Interpolation) -> Result<Self, String> {
+        let mut reader = Reader { bytes, offset: 0 };
+        if reader.take(4)? != MAGIC {
+            return Err("not a voxel grid".to_string());
+        }
+        let version = reader.u32()?;
+        if version != VERSION {
+            return Err(format!("unsupported voxel grid version {}", version));
+        }
+        let resolution = (reader.u32()? as usize, reader.u32()? as usize, reader.u32()? as usize);
+        let min = Vec3::new(reader.f64()?, reader.f64()?, reader.f64()?);
+        let max = Vec3::new(reader.f64()?, reader.f64()?, reader.f64()?);
+        let count = resolution.0.checked_mul(resolution.1)
+            .and_then(|n| n.checked_mul(resolution.2))
+            .ok_or_else(|| "voxel grid too large".to_string())?;
+        let distances = (0..count).map(|_| reader.f32()).collect::<Result<Vec<f32>, String>>()?;
+        let grid = Self::new(Bounds::new(min, max), resolution, distances, interpolation)?;
+
+        let palette_size = reader.u32()? as usize;
+        if palette_size == 0 {
+            return Ok(grid);
+        }
+        let mut palette = vec![];
+        for _ in 0..palette_size {
+            let mut values = [0.; MATERIAL_VALUES];
+            for value in values.iter_mut() {
+                *value = reader.f64()?;
+            }
+            palette.push(material_from_values(&values));
+        }
+        let material_ids = (0..count).map(|_| reader.u16()).collect::<Result<Vec<u16>, String>>()?;
+        grid.with_materials(material_ids, palette)
+    }
+
+    /// Browsers can't write files, so this is only around natively.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        std::fs::write(path, self.to_bytes()).map_err(|e| format!("couldn't write {}: {}", path, e))
+    }
+
+    fn index(&self, x: usize, y: usize, z: usize) -> usize {
+        x + self.resolution.0 * (y + self.resolution.1 * z)
+    }
+
+    fn cell_size(&self) -> Vec3 {
+        let (nx, ny, nz) = self.resolution;
+        let size = self.bounds.size();
+        Vec3::new(
+            size.x / (nx - 1) as f64,
+            size.y / (ny - 1) as f64,
+            size.z / (nz - 1) as f64,
+        )
+    }
+
+    fn sample_point(&self, x: usize, y: usize, z: usize) -> Vec3 {
+        let cell = self.cell_size();
+        self.bounds.min.clone().add(1., &Vec3::new(x as f64, y as f64, z as f64).scale_vec(&cell))
+    }
+
+    /// The sample at the given (possibly out of range) grid coordinates, clamped to the edges.
+    fn sample(&self, x: i64, y: i64, z: i64) -> f64 {
+        let (nx, ny, nz) = self.resolution;
+        let clamp = |i: i64, n: usize| i.max(0).min(n as i64 - 1) as usize;
+        self.distances[self.index(clamp(x, nx), clamp(y, ny), clamp(z, nz))] as f64
+    }
+
+    /// Continuous grid coordinates of the point, clamped to the grid.
+    fn grid_coordinates(&self, point: &Vec3) -> Vec3 {
+        let (nx, ny, nz) = self.resolution;
+        let cell = self.cell_size();
+        let local = point - &self.bounds.min;
+        Vec3::new(
+            (local.x / cell.x).max(0.).min((nx - 1) as f64),
+            (local.y / cell.y).max(0.).min((ny - 1) as f64),
+            (local.z / cell.z).max(0.).min((nz - 1) as f64),
+        )
+    }
+
+    fn interpolate(&self, point: &Vec3) -> f64 {
+        let g = self.grid_coordinates(point);
+        let (x, y, z) = (g.x.floor() as i64, g.y.floor() as i64, g.z.floor() as i64);
+        let (tx, ty, tz) = (g.x - x as f64, g.y - y as f64, g.z - z as f64);
+        match self.interpolation {
+            Interpolation::Trilinear => {
+                let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+                let plane = |dz: i64| lerp(
+                    lerp(self.sample(x, y, z + dz), self.sample(x + 1, y, z + dz), tx),
+                    lerp(self.sample(x, y + 1, z + dz), self.sample(x + 1, y + 1, z + dz), tx),
+                    ty,
+                );
+                lerp(plane(0), plane(1), tz)
+            }
+            Interpolation::Tricubic => {
+                let row = |dy: i64, dz: i64| catmull_rom(
+                    taps(|dx| self.sample(x + dx, y + dy, z + dz)),
+                    tx,
+                );
+                let plane = |dz: i64| catmull_rom(taps(|dy| row(dy, dz)), ty);
+                catmull_rom(taps(plane), tz)
+            }
+        }
+    }
+
+    fn palette_id(&mut self, material: Material) -> Result<u16, String> {
+        if let Some(id) = self.palette.iter().position(|m| m == &material) {
+            return Ok(id as u16);
+        }
+        if self.palette.len() >= NO_MATERIAL as usize {
+            return Err("too many distinct materials to bake".to_string());
+        }
+        self.palette.push(material);
+        Ok((self.palette.len() - 1) as u16)
+    }
+}
+
+impl SDF for VoxelGrid {
+    fn distance(&self, point: &Vec3) -> f64 {
+        // outside the grid, everything in the bounds is past the nearest point on them, at
+        // right angles to the way there, so the surface is at least this far.
+        let distance = self.interpolate(point);
+        let outside = self.bounds.distance(point);
+        if outside <= 0. {
+            return distance;
+        }
+        (outside * outside + distance.max(0.).powi(2)).sqrt()
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.epsilon
+    }
+
+    fn material(&self, point: &Vec3) -> Option<Material> {
+        let material_ids = self.material_ids.as_ref()?;
+        let g = self.grid_coordinates(point);
+        let id = material_ids[self.index(
+            g.x.round() as usize,
+            g.y.round() as usize,
+            g.z.round() as usize,
+        )];
+        self.palette.get(id as usize).cloned()
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        Some(self.bounds.clone())
+    }
+}
+
+/// Interpolates between the middle two of four evenly spaced samples.
+fn catmull_rom(p: [f64; 4], t: f64) -> f64 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    0.5 * ((2. * p[1])
+        + (p[2] - p[0]) * t
+        + (2. * p[0] - 5. * p[1] + 4. * p[2] - p[3]) * t2
+        + (3. * p[1] - p[0] - 3. * p[2] + p[3]) * t3)
+}
+
+/// The four samples catmull_rom needs, at offsets -1 through 2.
+fn taps<F: Fn(i64) -> f64>(f: F) -> [f64; 4] {
+    [f(-1), f(0), f(1), f(2)]
+}
+
+/// The phong values come first, then a flag for whether there's a pbr material and its values,
+/// then the absorption.
+const MATERIAL_VALUES: usize = 23;
+
+fn material_values(m: &Material) -> [f64; MATERIAL_VALUES] {
+    let (ar, ag, ab) = (&m.ambient).into();
+    let (dr, dg, db) = (&m.diffuse).into();
+    let (sr, sg, sb) = (&m.specular).into();
+    let (xr, xg, xb) = (&m.absorption).into();
+    let (has_pbr, (br, bg, bb), metallic, roughness) = match &m.pbr {
+        Some(pbr) => (1., (&pbr.base_color).into(), pbr.metallic, pbr.roughness),
+        None => (0., (0., 0., 0.), 0., 0.),
+    };
+    [
+        ar, ag, ab, dr, dg, db, sr, sg, sb,
+        m.phong, m.reflectivity, m.opacity, m.index_of_refraction,
+        has_pbr, br, bg, bb, metallic, roughness,
+        xr, xg, xb, m.density,
+    ]
+}
+
+fn material_from_values(v: &[f64; MATERIAL_VALUES]) -> Material {
+    let mut m = Material::new();
+    m.ambient = Color::new(v[0], v[1], v[2]);
+    m.diffuse = Color::new(v[3], v[4], v[5]);
+    m.specular = Color::new(v[6], v[7], v[8]);
+    m.phong = v[9];
+    m.reflectivity = v[10];
+    m.opacity = v[11];
+    m.index_of_refraction = v[12];
+    if v[13] != 0. {
+        m.pbr = Some(Pbr::new(Color::new(v[14], v[15], v[16]), v[17], v[18]));
+    }
+    m.absorption = Color::new(v[19], v[20], v[21]);
+    m.density = v[22];
+    m
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let end = self.offset + count;
+        if end > self.bytes.len() {
+            return Err(format!("voxel grid ended early, after {} bytes", self.bytes.len()));
+        }
+        let bytes = &self.bytes[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> Result<f32, String> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn f64(&mut self) -> Result<f64, String> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::grid::*;
+    use crate::mat::{Color, Material};
+    use crate::sdf::Sphere;
+
+    fn baked_sphere(interpolation: Interpolation) -> VoxelGrid {
+        let mut red = Material::new();
+        red.diffuse = Color::new(1., 0., 0.);
+        red.pbr = Some(Pbr::new(Color::new(1., 0., 0.), 0.2, 0.6));
+        red.absorption = Color::new(0., 1., 1.);
+        red.density = 0.3;
+        VoxelGrid::bake(
+            &Sphere::new(1.).shaded(red),
+            Bounds::centered(Vec3::new(1.5, 1.5, 1.5)),
+            (31, 31, 31),
+            interpolation,
+        ).unwrap()
+    }
+
+    #[test]
+    fn baked_distances() {
+        let sphere = Sphere::new(1.);
+        for interpolation in &[Interpolation::Trilinear, Interpolation::Tricubic] {
+            let grid = baked_sphere(*interpolation);
+            for point in &[
+                Vec3::zero(),
+                Vec3::new(0.33, 0.71, -0.2),
+                Vec3::new(1.2, -0.43, 0.05),
+                Vec3::new(-0.6, 0.6, 0.6),
+            ] {
+                let error = (grid.distance(point) - sphere.distance(point)).abs();
+                assert!(error < 0.01, "{:?} off by {} at {}", interpolation, error, point);
+            }
+            // outside the grid, we're still at least as far as the surface.
+            assert!(grid.distance(&Vec3::new(4., 0., 0.)) > 2.5);
+            // off the axes, where adding the distances would overshoot the surface.
+            let corner = Vec3::new(2.5, 1.5, 0.);
+            let distance = grid.distance(&corner);
+            assert!(distance > 1. && distance <= sphere.distance(&corner), "{}", distance);
+            assert_eq!(grid.material(&Vec3::new(1., 0., 0.)).unwrap().diffuse.to_string(), "#ff0000");
+        }
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let grid = baked_sphere(Interpolation::Tricubic);
+        let loaded = VoxelGrid::from_bytes(&grid.to_bytes(), Interpolation::Tricubic).unwrap();
+        assert_eq!(loaded.resolution, grid.resolution);
+        assert_eq!(loaded.distances, grid.distances);
+        assert_eq!(loaded.palette, grid.palette);
+        assert_eq!(loaded.material_ids, grid.material_ids);
+
+        let bytes = grid.to_bytes();
+        assert!(VoxelGrid::from_bytes(&bytes[..bytes.len() - 1], Interpolation::Trilinear).is_err());
+        assert!(VoxelGrid::from_bytes(b"nope", Interpolation::Trilinear).is_err());
+        let mut other_version = bytes.clone();
+        other_version[4] = 2;
+        assert!(VoxelGrid::from_bytes(&other_version, Interpolation::Trilinear).is_err());
+
+        // a resolution whose sample count doesn't fit in a usize.
+        let mut huge = bytes[..8].to_vec();
+        for _ in 0..3 {
+            huge.extend(&u32::MAX.to_le_bytes());
+        }
+        huge.extend(&bytes[20..]);
+        let error = VoxelGrid::from_bytes(&huge, Interpolation::Trilinear).err();
+        assert_eq!(error, Some("voxel grid too large".to_string()));
+    }
+}
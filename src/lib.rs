@@ -1,7 +1,22 @@
+mod buffer;
+mod brdf;
+mod curve;
+mod fractal;
+mod grid;
+mod light;
 mod linear;
+mod mesh;
 mod mat;
+mod noise;
+mod occlusion;
+mod path;
+mod rng;
+mod sample;
 mod scene;
 mod sdf;
+mod sdf2;
+mod terrain;
+mod text;
 mod utils;
 mod viewport;
 
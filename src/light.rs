@@ -0,0 +1,271 @@
+use std::f64::consts::PI;
+
+use crate::linear::*;
+use crate::mat::Color;
+use crate::rng::Rng;
+
+/// Sunlight comes from this far away, which is far enough that the scene can't tell the
+/// difference from infinitely far.
+const SUN_DISTANCE: f64 = 1e4;
+
+pub struct Light {
+    pub position: Vec3,
+    pub color: Color,
+    pub atten: f64,
+    pub shape: LightShape,
+    pub kind: LightKind,
+}
+
+/// Which way a light shines, and how it fades.
+#[derive(Clone, Debug)]
+pub enum LightKind {
+    /// Shines every way from its position, fading with distance.
+    Point,
+    /// Parallel rays all going in one direction, as bright everywhere. The position is ignored.
+    Directional { direction: Vec3 },
+    /// A point light that only shines within `cos_outer` of its direction, fading out from
+    /// full brightness at `cos_inner` to nothing at the edge of the cone.
+    Spot { direction: Vec3, cos_inner: f64, cos_outer: f64 },
+    /// Light from the whole sky, with the light's color above and `ground` below, that doesn't
+    /// cast shadows. The position is ignored.
+    Hemisphere { up: Vec3, ground: Color },
+}
+
+/// What a light's glowing surface looks like, centered on its position. Anything bigger than a
+/// point casts soft shadows, since parts of it can be hidden while others aren't.
+#[derive(Clone, Debug)]
+pub enum LightShape {
+    Point,
+    Sphere { radius: f64 },
+    /// A parallelogram, given by the vectors from its center to the middles of two of its edges.
+    Rect { u: Vec3, v: Vec3 },
+    Disk { normal: Vec3, radius: f64 },
+}
+
+impl Light {
+    pub fn new(position: Vec3, color: Color, atten: f64) -> Self {
+        Self { position, color, atten, shape: LightShape::Point, kind: LightKind::Point }
+    }
+
+    /// Sunlight shining in `direction`.
+    pub fn sun(direction: Vec3, color: Color) -> Self {
+        let mut light = Self::new(Vec3::zero(), color, 1.);
+        light.kind = LightKind::Directional { direction: direction.normalize() };
+        light
+    }
+
+    /// Ambient light from the sky above and the ground below.
+    pub fn sky(sky: Color, ground: Color) -> Self {
+        let mut light = Self::new(Vec3::zero(), sky, 1.);
+        light.kind = LightKind::Hemisphere { up: Vec3::up(), ground };
+        light
+    }
+
+    /// Narrows the light into a cone around `direction`, `angle` degrees from its middle to its
+    /// edge. `falloff` is how much of the cone, from 0 to 1, it takes to fade out at the edge.
+    pub fn spot(mut self, direction: Vec3, angle: f64, falloff: f64) -> Self {
+        let outer = angle.to_radians();
+        let inner = outer * (1. - falloff.clamp(0., 1.));
+        self.kind = LightKind::Spot {
+            direction: direction.normalize(),
+            cos_inner: inner.cos(),
+            cos_outer: outer.cos(),
+        };
+        self
+    }
+
+    pub fn sphere(mut self, radius: f64) -> Self {
+        self.shape = LightShape::Sphere { radius };
+        self
+    }
+
+    pub fn rect(mut self, u: Vec3, v: Vec3) -> Self {
+        self.shape = LightShape::Rect { u, v };
+        self
+    }
+
+    pub fn disk(mut self, normal: Vec3, radius: f64) -> Self {
+        self.shape = LightShape::Disk { normal: normal.normalize(), radius };
+        self
+    }
+
+    pub fn shadow_ray(&self, point: &Vec3) -> Ray {
+        Ray::new(point.clone(), &self.center(point) - point)
+    }
+
+    /// Where the light comes from, as seen from the point.
+    fn center(&self, point: &Vec3) -> Vec3 {
+        match &self.kind {
+            LightKind::Directional { direction } => point.clone().add(-SUN_DISTANCE, direction),
+            _ => self.position.clone(),
+        }
+    }
+
+    /// A random point on the light, as seen from `from`.
+    pub fn sample_point(&self, from: &Vec3, rng: &mut Rng) -> Vec3 {
+        if let LightKind::Directional { .. } = self.kind {
+            return self.center(from);
+        }
+        match &self.shape {
+            LightShape::Point => self.position.clone(),
+            LightShape::Sphere { radius } => {
+                // from any one point, a sphere looks like a disk facing it.
+                let facing = from - &self.position;
+                if facing.norm2() == 0. {
+                    return self.position.clone();
+                }
+                self.disk_point(&facing.normalize(), *radius, rng)
+            }
+            LightShape::Rect { u, v } => self.position.clone()
+                .add(rng.range(-1., 1.), u)
+                .add(rng.range(-1., 1.), v),
+            LightShape::Disk { normal, radius } => self.disk_point(normal, *radius, rng),
+        }
+    }
+
+    /// How much a flat light's surface faces the point, from the spot on it at `target`.
+    /// Rectangles and disks shine from both faces, but less the more edge-on they're seen, like
+    /// any flat surface that glows evenly. Everything else looks the same from every side.
+    pub fn facing(&self, target: &Vec3, point: &Vec3) -> f64 {
+        let normal = match &self.shape {
+            LightShape::Rect { u, v } => (u ^ v).normalize(),
+            LightShape::Disk { normal, .. } => normal.clone(),
+            _ => return 1.,
+        };
+        if let LightKind::Directional { .. } = self.kind {
+            return 1.;
+        }
+        let to_point = point - target;
+        if to_point.norm2() == 0. {
+            return 1.;
+        }
+        (&to_point.normalize() * &normal).abs()
+    }
+
+    /// A point picked uniformly from the disk around the light's position.
+    fn disk_point(&self, normal: &Vec3, radius: f64, rng: &mut Rng) -> Vec3 {
+        let r = radius * rng.next_f64().sqrt();
+        let theta = 2. * PI * rng.next_f64();
+        let offset = Basis::around(normal).project(&Vec3::new(r * theta.cos(), r * theta.sin(), 0.));
+        &self.position + &offset
+    }
+
+    /// How bright the light is at the point, before anything gets in the way. Ambient lights
+    /// don't shine from anywhere in particular, so they're black here; see `ambient`.
+    pub fn color(&self, point: &Vec3) -> Color {
+        let cone = match &self.kind {
+            LightKind::Directional { .. } => return self.color.clone(),
+            LightKind::Hemisphere { .. } => return Color::black(),
+            LightKind::Point => 1.,
+            LightKind::Spot { direction, cos_inner, cos_outer } => {
+                let cos = &(point - &self.position).normalize() * direction;
+                smoothstep(*cos_outer, *cos_inner, cos)
+            }
+        };
+        let dist2 = point.dist2(&self.position);
+        let atten = ((self.atten * self.atten) / dist2).min(1.0);
+        self.color.clone().scale(atten * cone)
+    }
+
+    /// The light a diffuse surface facing `normal` gets from an ambient light, if this is one.
+    /// It's the average of what the surface sees, which blends from the ground to the sky as it
+    /// turns upwards.
+    pub fn ambient(&self, normal: &Vec3) -> Option<Color> {
+        match &self.kind {
+            LightKind::Hemisphere { up, ground } => {
+                let t = (normal * up + 1.) / 2.;
+                Some(ground.clone().lerp(t, &self.color))
+            }
+            _ => None,
+        }
+    }
+
+    /// The light seen looking off into the distance in `direction`, for ambient lights.
+    pub fn background(&self, direction: &Vec3) -> Option<Color> {
+        match &self.kind {
+            LightKind::Hemisphere { up, ground } if direction * up < 0. => Some(ground.clone()),
+            LightKind::Hemisphere { .. } => Some(self.color.clone()),
+            _ => None,
+        }
+    }
+}
+
+fn smoothstep(edge0: f64, edge1: f64, x: f64) -> f64 {
+    if edge0 >= edge1 {
+        return if x >= edge1 { 1. } else { 0. };
+    }
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0., 1.);
+    t * t * (3. - 2. * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::light::*;
+
+    #[test]
+    fn area_light_samples() {
+        let mut rng = Rng::new(9, 0);
+        let center = Vec3::new(1., 2., 3.);
+        let from = Vec3::new(1., -10., 3.);
+        let sphere = Light::new(center.clone(), Color::white(), 1.).sphere(0.5);
+        let rect = Light::new(center.clone(), Color::white(), 1.)
+            .rect(Vec3::new(2., 0., 0.), Vec3::new(0., 0., 1.));
+        let disk = Light::new(center.clone(), Color::white(), 1.).disk(Vec3::new(0., 2., 0.), 1.);
+        let mut spread = 0.;
+        for _ in 0..100 {
+            let p = sphere.sample_point(&from, &mut rng);
+            assert!(p.dist(&center) <= 0.5 + 1e-9);
+            // the disk we sample faces the point being lit.
+            assert!((&(&p - &center) * &Vec3::up()).abs() < 1e-9);
+
+            let p = &rect.sample_point(&from, &mut rng) - &center;
+            assert!(p.x.abs() <= 2. && p.y == 0. && p.z.abs() <= 1.);
+
+            let p = &disk.sample_point(&from, &mut rng) - &center;
+            assert!(p.norm() <= 1. + 1e-9 && p.y.abs() < 1e-9);
+            spread += p.norm() / 100.;
+        }
+        // uniform over the disk's area, so the average distance from the center is 2/3.
+        assert!((spread - 2. / 3.).abs() < 0.1, "{}", spread);
+        assert_eq!(Light::new(center.clone(), Color::white(), 1.).sample_point(&from, &mut rng).dist(&center), 0.);
+    }
+
+    #[test]
+    fn flat_lights_fade_edge_on() {
+        let center = Vec3::zero();
+        let disk = Light::new(center.clone(), Color::white(), 1.).disk(Vec3::up(), 1.);
+        assert_eq!(disk.facing(&center, &Vec3::new(0., 3., 0.)), 1.);
+        // both faces shine.
+        assert_eq!(disk.facing(&center, &Vec3::new(0., -3., 0.)), 1.);
+        assert!((disk.facing(&center, &Vec3::new(3., 3., 0.)) - 0.5f64.sqrt()).abs() < 1e-9);
+        assert!(disk.facing(&center, &Vec3::new(3., 0., 0.)).abs() < 1e-9);
+
+        let rect = Light::new(center.clone(), Color::white(), 1.)
+            .rect(Vec3::new(2., 0., 0.), Vec3::new(0., 0., 1.));
+        assert!((rect.facing(&center, &Vec3::new(0., -3., 0.)) - 1.).abs() < 1e-9);
+        let sphere = Light::new(center.clone(), Color::white(), 1.).sphere(1.);
+        assert_eq!(sphere.facing(&center, &Vec3::new(3., 0., 0.)), 1.);
+    }
+
+    #[test]
+    fn light_kinds() {
+        let sun = Light::sun(Vec3::new(0., -2., 0.), Color::white());
+        assert_eq!(sun.color(&Vec3::new(100., 0., 3.)), Color::white());
+        // shadow rays head back up towards the sun.
+        let toward = sun.shadow_ray(&Vec3::zero()).direction.normalize();
+        assert!((&toward * &Vec3::up() - 1.).abs() < 1e-9);
+
+        let spot = Light::new(Vec3::zero(), Color::white(), 100.).spot(Vec3::new(0., 0., -1.), 30., 0.5);
+        assert_eq!(spot.color(&Vec3::new(0., 0., -5.)), Color::white());
+        assert_eq!(spot.color(&Vec3::new(5., 0., -5.)), Color::black());
+        let (edge, _, _): (f64, f64, f64) = (&spot.color(&Vec3::new(0., 2.2, -5.))).into();
+        assert!(0. < edge && edge < 1., "{}", edge);
+
+        let sky = Light::sky(Color::new(0., 0., 1.), Color::new(1., 0., 0.));
+        assert!(sky.color(&Vec3::zero()).is_black());
+        assert_eq!(sky.ambient(&Vec3::up()), Some(Color::new(0., 0., 1.)));
+        assert_eq!(sky.ambient(&Vec3::new(1., 0., 0.)), Some(Color::new(0.5, 0., 0.5)));
+        assert_eq!(sky.background(&Vec3::new(1., -1., 0.)), Some(Color::new(1., 0., 0.)));
+        assert_eq!(sun.ambient(&Vec3::up()), None);
+    }
+}
@@ -9,6 +9,12 @@ pub struct Vec3 {
     pub z: f64,
 }
 
+#[derive(Clone, Debug)]
+pub struct Vec2 {
+    pub x: f64,
+    pub y: f64,
+}
+
 #[derive(Clone, Debug)]
 pub struct Basis {
     pub axes: (Vec3, Vec3, Vec3),
@@ -26,6 +32,13 @@ pub struct Ray {
     pub direction: Vec3,
 }
 
+/// An axis-aligned bounding box.
+#[derive(Clone, Debug)]
+pub struct Bounds {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
 impl Vec3 {
     pub fn new(x: f64, y: f64, z: f64) -> Self {
         Self { x, y, z }
@@ -166,6 +179,57 @@ impl Vec3 {
     }
 }
 
+impl Vec2 {
+    pub fn new(x: f64, y: f64) -> Self {
+        Self { x, y }
+    }
+
+    pub fn zero() -> Self {
+        Self::new(0., 0.)
+    }
+
+    /// z component of the 3d cross product of the two vectors.
+    pub fn cross(a: &Self, b: &Self) -> f64 {
+        a.x * b.y - a.y * b.x
+    }
+
+    pub fn add(mut self, scale: f64, other: &Vec2) -> Self {
+        self.x += scale * other.x;
+        self.y += scale * other.y;
+        self
+    }
+
+    pub fn scale(mut self, scale: f64) -> Self {
+        self.x *= scale;
+        self.y *= scale;
+        self
+    }
+
+    pub fn dot(&self, other: &Vec2) -> f64 {
+        self.x * other.x + self.y * other.y
+    }
+
+    pub fn norm2(&self) -> f64 {
+        self.dot(self)
+    }
+
+    pub fn norm(&self) -> f64 {
+        self.norm2().sqrt()
+    }
+
+    pub fn normalize(self) -> Self {
+        let mag2 = self.norm2();
+        if mag2 == 0.0 || mag2 == 1.0 {
+            return self;
+        }
+        self.scale(1. / mag2.sqrt())
+    }
+
+    pub fn dist(&self, other: &Vec2) -> f64 {
+        (other - self).norm()
+    }
+}
+
 impl Basis {
     pub fn new(i: Vec3, j: Vec3, k: Vec3) -> Self {
         Self {
@@ -177,6 +241,20 @@ impl Basis {
         Self::new(Vec3::right(), Vec3::up(), Vec3::forward())
     }
 
+    /// Some orthonormal basis whose third axis is the given unit vector, for working in the
+    /// space around a surface normal.
+    pub fn around(k: &Vec3) -> Self {
+        // https://graphics.pixar.com/library/OrthonormalB/paper.pdf
+        let sign = 1f64.copysign(k.z);
+        let a = -1. / (sign + k.z);
+        let b = k.x * k.y * a;
+        Self::new(
+            Vec3::new(1. + sign * k.x * k.x * a, sign * b, -sign * k.x),
+            Vec3::new(b, sign + k.y * k.y * a, -k.y),
+            k.clone(),
+        )
+    }
+
     pub fn scale(mut self, scale: f64) -> Self {
         self.axes.0 = self.axes.0.scale(scale);
         self.axes.1 = self.axes.1.scale(scale);
@@ -252,6 +330,189 @@ impl Ray {
     }
 }
 
+impl Bounds {
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    /// Bounds centered on the origin.
+    pub fn centered(half_size: Vec3) -> Self {
+        Self::new(half_size.clone().scale(-1.), half_size)
+    }
+
+    pub fn around<'a, I>(points: I) -> Self where I: IntoIterator<Item=&'a Vec3> {
+        let inf = f64::INFINITY;
+        points.into_iter().fold(
+            Self::new(Vec3::new(inf, inf, inf), Vec3::new(-inf, -inf, -inf)),
+            |bounds, p| bounds.union(&Self::new(p.clone(), p.clone())),
+        )
+    }
+
+    pub fn union(&self, other: &Bounds) -> Self {
+        Self::new(
+            Vec3::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            Vec3::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        )
+    }
+
+    pub fn intersection(&self, other: &Bounds) -> Self {
+        Self::new(
+            Vec3::new(
+                self.min.x.max(other.min.x),
+                self.min.y.max(other.min.y),
+                self.min.z.max(other.min.z),
+            ),
+            Vec3::new(
+                self.max.x.min(other.max.x),
+                self.max.y.min(other.max.y),
+                self.max.z.min(other.max.z),
+            ),
+        )
+    }
+
+    pub fn expand(self, amount: &Vec3) -> Self {
+        Self::new(self.min.add(-1., amount), self.max.add(1., amount))
+    }
+
+    pub fn translate(self, by: &Vec3) -> Self {
+        Self::new(self.min.add(1., by), self.max.add(1., by))
+    }
+
+    pub fn corners(&self) -> Vec<Vec3> {
+        (0..8).map(|i| Vec3::new(
+            if i & 1 == 0 { self.min.x } else { self.max.x },
+            if i & 2 == 0 { self.min.y } else { self.max.y },
+            if i & 4 == 0 { self.min.z } else { self.max.z },
+        )).collect()
+    }
+
+    pub fn center(&self) -> Vec3 {
+        self.min.clone().lerp(0.5, &self.max)
+    }
+
+    pub fn size(&self) -> Vec3 {
+        &self.max - &self.min
+    }
+
+    /// Distance from the point to the box, or 0 if the point is inside.
+    pub fn distance(&self, point: &Vec3) -> f64 {
+        let outside = |c: f64, min: f64, max: f64| (min - c).max(c - max).max(0.);
+        Vec3::new(
+            outside(point.x, self.min.x, self.max.x),
+            outside(point.y, self.min.y, self.max.y),
+            outside(point.z, self.min.z, self.max.z),
+        ).norm()
+    }
+}
+
+/// Which part of a triangle a closest point lies on. Vertices are numbered a, b, c, and edges
+/// are numbered ab, bc, ca.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TriangleFeature {
+    Vertex(usize),
+    Edge(usize),
+    Face,
+}
+
+/// Finds the point on the triangle abc closest to p.
+pub fn closest_point_on_triangle(p: &Vec3, a: &Vec3, b: &Vec3, c: &Vec3) -> Vec3 {
+    closest_feature_on_triangle(p, a, b, c).0
+}
+
+/// Finds the point on the triangle abc closest to p, and which feature of the triangle it's on.
+pub fn closest_feature_on_triangle(
+    p: &Vec3, a: &Vec3, b: &Vec3, c: &Vec3,
+) -> (Vec3, TriangleFeature) {
+    // Ericson, Real-Time Collision Detection, 5.1.5
+    let ab = b - a;
+    let ac = c - a;
+    let ap = p - a;
+    let d1 = &ab * &ap;
+    let d2 = &ac * &ap;
+    if d1 <= 0. && d2 <= 0. {
+        return (a.clone(), TriangleFeature::Vertex(0));
+    }
+    let bp = p - b;
+    let d3 = &ab * &bp;
+    let d4 = &ac * &bp;
+    if d3 >= 0. && d4 <= d3 {
+        return (b.clone(), TriangleFeature::Vertex(1));
+    }
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0. && d1 >= 0. && d3 <= 0. {
+        return (a.clone().add(d1 / (d1 - d3), &ab), TriangleFeature::Edge(0));
+    }
+    let cp = p - c;
+    let d5 = &ab * &cp;
+    let d6 = &ac * &cp;
+    if d6 >= 0. && d5 <= d6 {
+        return (c.clone(), TriangleFeature::Vertex(2));
+    }
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0. && d2 >= 0. && d6 <= 0. {
+        return (a.clone().add(d2 / (d2 - d6), &ac), TriangleFeature::Edge(2));
+    }
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0. && (d4 - d3) >= 0. && (d5 - d6) >= 0. {
+        let t = (d4 - d3) / ((d4 - d3) + (d5 - d6));
+        return (b.clone().add(t, &(c - b)), TriangleFeature::Edge(1));
+    }
+    let denom = 1. / (va + vb + vc);
+    (a.clone().add(vb * denom, &ab).add(vc * denom, &ac), TriangleFeature::Face)
+}
+
+impl ops::Add<&Vec2> for &Vec2 {
+    type Output = Vec2;
+
+    fn add(self, rhs: &Vec2) -> Self::Output {
+        Vec2::new(self.x + rhs.x, self.y + rhs.y)
+    }
+}
+
+impl ops::Sub<&Vec2> for &Vec2 {
+    type Output = Vec2;
+
+    fn sub(self, rhs: &Vec2) -> Self::Output {
+        Vec2::new(self.x - rhs.x, self.y - rhs.y)
+    }
+}
+
+impl ops::Mul<&Vec2> for &Vec2 {
+    type Output = f64;
+
+    fn mul(self, rhs: &Vec2) -> Self::Output {
+        self.dot(rhs)
+    }
+}
+
+impl ops::Mul<f64> for &Vec2 {
+    type Output = Vec2;
+
+    fn mul(self, rhs: f64) -> Self::Output {
+        self.clone().scale(rhs)
+    }
+}
+
+impl fmt::Display for Vec2 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(f, "<{:.2}, {:.2}>", self.x, self.y)
+    }
+}
+
+impl From<(f64, f64)> for Vec2 {
+    fn from(tup: (f64, f64)) -> Self {
+        Self::new(tup.0, tup.1)
+    }
+}
+
 impl ops::Add<&Vec3> for &Vec3 {
     type Output = Vec3;
 
@@ -380,5 +641,12 @@ mod tests {
     }
 
     #[test]
-    fn basis() {}
+    fn basis() {
+        for k in &[Vec3::up(), Vec3::backward(), Vec3::new(1., -2., 0.5).normalize()] {
+            let Basis { axes: (i, j, k2) } = Basis::around(k);
+            assert!((i.norm() - 1.).abs() < 1e-12 && (j.norm() - 1.).abs() < 1e-12);
+            assert!((&i * &j).abs() < 1e-12 && (&i * k).abs() < 1e-12 && (&j * k).abs() < 1e-12);
+            assert!((&i ^ &j).dist(&k2) < 1e-12);
+        }
+    }
 }
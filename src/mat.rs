@@ -12,7 +12,7 @@ impl RefractionConstants {
     pub const DIAMOND: f64 = 2.417;
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Material {
     pub ambient: Color,
     pub diffuse: Color,
@@ -21,6 +21,24 @@ pub struct Material {
     pub reflectivity: f64,
     pub opacity: f64,
     pub index_of_refraction: f64,
+    /// How strongly the inside of a transparent material absorbs each color, per unit of
+    /// distance light travels through it, scaled by `density`.
+    pub absorption: Color,
+    pub density: f64,
+    /// If set, lights are shaded with this instead of the phong terms above.
+    pub pbr: Option<Pbr>,
+}
+
+/// A metallic-roughness material, like the ones glTF and most game engines use. See
+/// `brdf.rs` for how it's shaded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pbr {
+    /// The diffuse color of dielectrics, and the color of a metal's reflections.
+    pub base_color: Color,
+    /// From 0 for dielectrics (plastic, wood, stone) to 1 for bare metal.
+    pub metallic: f64,
+    /// From 0 for a perfect mirror to 1 for a completely matte surface.
+    pub roughness: f64,
 }
 
 impl Material {
@@ -33,11 +51,69 @@ impl Material {
             reflectivity: 0.,
             opacity: 1.0,
             index_of_refraction: RefractionConstants::VACUUM,
+            absorption: Color::black(),
+            density: 0.,
+            pbr: None,
         }
     }
+
+    pub fn lerp(mut self, s: f64, other: &Material) -> Self {
+        let mix = |a: f64, b: f64| (1.0 - s) * a + s * b;
+        self.ambient = self.ambient.lerp(s, &other.ambient);
+        self.diffuse = self.diffuse.lerp(s, &other.diffuse);
+        self.specular = self.specular.lerp(s, &other.specular);
+        self.phong = mix(self.phong, other.phong);
+        self.reflectivity = mix(self.reflectivity, other.reflectivity);
+        self.opacity = mix(self.opacity, other.opacity);
+        self.index_of_refraction = mix(self.index_of_refraction, other.index_of_refraction);
+        self.absorption = self.absorption.lerp(s, &other.absorption);
+        self.density = mix(self.density, other.density);
+        self.pbr = match (self.pbr, &other.pbr) {
+            (Some(a), Some(b)) => Some(a.lerp(s, b)),
+            // phong and pbr can't be blended, so take whichever is closer.
+            (pbr, _) if s < 0.5 => pbr,
+            (_, pbr) => pbr.clone(),
+        };
+        self
+    }
+
+    /// How much of each color is left after travelling this far through the material, by the
+    /// Beer-Lambert law.
+    pub fn transmittance(&self, distance: f64) -> Color {
+        let absorbed = self.absorption.clone().scale(self.density * distance.max(0.));
+        Color::new((-absorbed.r).exp(), (-absorbed.g).exp(), (-absorbed.b).exp())
+    }
+}
+
+impl Pbr {
+    pub fn new(base_color: Color, metallic: f64, roughness: f64) -> Self {
+        Self { base_color, metallic: metallic.clamp(0., 1.), roughness: roughness.clamp(0., 1.) }
+    }
+
+    /// Something that looks about like the phong material does. Shinier highlights make for
+    /// smoother surfaces, and reflective ones are treated as metals, colored by the specular
+    /// color if there is one.
+    pub fn from_phong(material: &Material) -> Self {
+        // the beckmann roughness that matches a blinn-phong exponent, which is close to ggx's.
+        let alpha = (2. / (material.phong.max(0.) + 2.)).sqrt();
+        let metal_color = if material.specular.is_black() {
+            Color::white()
+        } else {
+            material.specular.clone()
+        };
+        let metallic = material.reflectivity.clamp(0., 1.);
+        Self::new(material.diffuse.clone().lerp(metallic, &metal_color), metallic, alpha.sqrt())
+    }
+
+    pub fn lerp(mut self, s: f64, other: &Pbr) -> Self {
+        self.base_color = self.base_color.lerp(s, &other.base_color);
+        self.metallic = (1.0 - s) * self.metallic + s * other.metallic;
+        self.roughness = (1.0 - s) * self.roughness + s * other.roughness;
+        self
+    }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Color {
     r: f64,
     g: f64,
@@ -135,6 +211,12 @@ impl fmt::Display for Color {
     }
 }
 
+impl From<&Color> for (f64, f64, f64) {
+    fn from(color: &Color) -> Self {
+        (color.r, color.g, color.b)
+    }
+}
+
 impl ops::Add<&Color> for &Color {
     type Output = Color;
 
@@ -187,4 +269,17 @@ mod tests {
         assert_eq!(Color::from_hexstring("#ffffff").to_string(), "#ffffff");
         assert_eq!(Color::from_hexstring("#000000").to_string(), "#000000");
     }
+
+    #[test]
+    fn beer_lambert() {
+        let mut material = Material::new();
+        assert_eq!(material.transmittance(10.), Color::white());
+        material.absorption = Color::new(0., 1., 2.);
+        material.density = 0.5;
+        // twice as far lets through the square of the light.
+        let (r, g, b) = (&material.transmittance(2.)).into();
+        assert_eq!((r, g, b), (1., (-1f64).exp(), (-2f64).exp()));
+        let (_, g2, _) = (&material.transmittance(4.)).into();
+        assert!((g2 - g * g).abs() < 1e-12);
+    }
 }
@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+
+use crate::linear::*;
+use crate::sdf::SDF;
+
+/// A closed triangle mesh. Distances are exact, and the sign comes from the angle-weighted
+/// pseudo-normal of whichever vertex, edge or face is closest (Bærentzen & Aanæs 2005), so the
+/// mesh should be watertight with consistently wound (counter-clockwise) faces.
+pub struct TriangleMesh {
+    vertices: Vec<Vec3>,
+    triangles: Vec<Triangle>,
+    vertex_normals: Vec<Vec3>,
+    nodes: Vec<BvhNode>,
+    epsilon: f64,
+}
+
+struct Triangle {
+    indices: [usize; 3],
+    normal: Vec3,
+    /// pseudo-normals of the edges ab, bc and ca.
+    edge_normals: [Vec3; 3],
+}
+
+enum BvhNode {
+    Leaf { bounds: Bounds, start: usize, end: usize },
+    Branch { bounds: Bounds, left: usize, right: usize },
+}
+
+struct Nearest {
+    distance2: f64,
+    point: Vec3,
+    pseudo_normal: Vec3,
+}
+
+const MAX_LEAF_SIZE: usize = 4;
+
+impl TriangleMesh {
+    pub fn new(vertices: Vec<Vec3>, faces: Vec<[usize; 3]>) -> Result<Self, String> {
+        if faces.is_empty() {
+            return Err("mesh has no faces".to_string());
+        }
+        if let Some(i) = faces.iter().flatten().find(|&&i| i >= vertices.len()) {
+            return Err(format!("face refers to vertex {}, but there are only {}", i, vertices.len()));
+        }
+
+        let face_normal = |f: &[usize; 3]| {
+            let (a, b, c) = (&vertices[f[0]], &vertices[f[1]], &vertices[f[2]]);
+            (&(b - a) ^ &(c - a)).normalize()
+        };
+
+        // every edge is shared by two faces, and its pseudo-normal is the sum of their normals.
+        let mut edge_normals: HashMap<(usize, usize), Vec3> = HashMap::new();
+        let mut vertex_normals = vec![Vec3::zero(); vertices.len()];
+        for f in &faces {
+            let normal = face_normal(f);
+            for i in 0..3 {
+                let (a, b, c) = (f[i], f[(i + 1) % 3], f[(i + 2) % 3]);
+                let key = (a.min(b), a.max(b));
+                let sum = edge_normals.remove(&key).unwrap_or_else(Vec3::zero);
+                edge_normals.insert(key, sum.add(1., &normal));
+
+                let u = (&vertices[b] - &vertices[a]).normalize();
+                let v = (&vertices[c] - &vertices[a]).normalize();
+                let angle = (&u * &v).clamp(-1., 1.).acos();
+                vertex_normals[a] = vertex_normals[a].clone().add(angle, &normal);
+            }
+        }
+
+        let triangles: Vec<Triangle> = faces.into_iter().map(|f| {
+            let edge = |i: usize| {
+                let (a, b) = (f[i], f[(i + 1) % 3]);
+                edge_normals[&(a.min(b), a.max(b))].clone().normalize()
+            };
+            Triangle {
+                normal: face_normal(&f),
+                edge_normals: [edge(0), edge(1), edge(2)],
+                indices: f,
+            }
+        }).collect();
+
+        let vertex_normals = vertex_normals.into_iter().map(|n| n.normalize()).collect();
+        let mut mesh = Self { vertices, triangles, vertex_normals, nodes: vec![], epsilon: 0. };
+        let count = mesh.triangles.len();
+        mesh.build_bvh(0, count);
+        mesh.epsilon = mesh.nodes[0].bounds().size().norm() / 10_000.0;
+        Ok(mesh)
+    }
+
+    /// Parses a Wavefront OBJ file, using only its vertex positions and faces. Polygonal faces
+    /// are split into triangle fans.
+    pub fn from_obj(source: &str) -> Result<Self, String> {
+        let mut vertices: Vec<Vec3> = vec![];
+        let mut faces: Vec<[usize; 3]> = vec![];
+        for (number, line) in source.lines().enumerate() {
+            let error = |message: &str| format!("line {}: {}: {}", number + 1, message, line);
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            match parts.split_first() {
+                Some((&"v", coords)) if coords.len() >= 3 => {
+                    let coords: Result<Vec<f64>, _> = coords[..3].iter().map(|c| c.parse()).collect();
+                    let coords = coords.map_err(|_| error("bad vertex"))?;
+                    vertices.push(Vec3::new(coords[0], coords[1], coords[2]));
+                }
+                Some((&"f", corners)) if corners.len() >= 3 => {
+                    let mut indices = vec![];
+                    for corner in corners {
+                        // corners look like v, v/vt, v//vn or v/vt/vn, and can count backwards
+                        // from the most recent vertex when negative.
+                        let index: i64 = corner.split('/').next().unwrap_or("")
+                            .parse()
+                            .map_err(|_| error("bad face"))?;
+                        let index = if index < 0 {
+                            vertices.len() as i64 + index
+                        } else {
+                            index - 1
+                        };
+                        if index < 0 || index as usize >= vertices.len() {
+                            return Err(error("face refers to a missing vertex"));
+                        }
+                        indices.push(index as usize);
+                    }
+                    for i in 1..indices.len() - 1 {
+                        faces.push([indices[0], indices[i], indices[i + 1]]);
+                    }
+                }
+                Some((&"v", _)) | Some((&"f", _)) => return Err(error("too few values")),
+                _ => {}
+            }
+        }
+        Self::new(vertices, faces)
+    }
+
+    fn triangle_bounds(&self, triangle: &Triangle) -> Bounds {
+        Bounds::around(triangle.indices.iter().map(|&i| &self.vertices[i]))
+    }
+
+    /// Builds the bvh node for the triangles in `start..end`, returning its index.
+    fn build_bvh(&mut self, start: usize, end: usize) -> usize {
+        let bounds = self.triangles[start..end].iter()
+            .map(|t| self.triangle_bounds(t))
+            .fold(self.triangle_bounds(&self.triangles[start]), |a, b| a.union(&b));
+        let index = self.nodes.len();
+        if end - start <= MAX_LEAF_SIZE {
+            self.nodes.push(BvhNode::Leaf { bounds, start, end });
+            return index;
+        }
+
+        // split at the median along the longest axis of the triangle centers.
+        let centers: Vec<Vec3> = self.triangles[start..end].iter()
+            .map(|t| self.triangle_bounds(t).center())
+            .collect();
+        let size = Bounds::around(&centers).size();
+        let axis = |v: &Vec3| if size.x >= size.y && size.x >= size.z {
+            v.x
+        } else if size.y >= size.z {
+            v.y
+        } else {
+            v.z
+        };
+        let vertices = &self.vertices;
+        self.triangles[start..end].sort_by(|a, b| {
+            let center = |t: &Triangle| t.indices.iter()
+                .fold(Vec3::zero(), |sum, &i| sum.add(1. / 3., &vertices[i]));
+            axis(&center(a)).partial_cmp(&axis(&center(b))).unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        // reserve our spot before the children are added.
+        self.nodes.push(BvhNode::Leaf { bounds: bounds.clone(), start, end });
+        let mid = (start + end) / 2;
+        let left = self.build_bvh(start, mid);
+        let right = self.build_bvh(mid, end);
+        self.nodes[index] = BvhNode::Branch { bounds, left, right };
+        index
+    }
+
+    fn nearest(&self, point: &Vec3) -> Nearest {
+        let mut nearest = Nearest {
+            distance2: f64::INFINITY,
+            point: point.clone(),
+            pseudo_normal: Vec3::up(),
+        };
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            let bounds_distance = node.bounds().distance(point);
+            if bounds_distance * bounds_distance >= nearest.distance2 {
+                continue;
+            }
+            match node {
+                BvhNode::Leaf { start, end, .. } => {
+                    for triangle in &self.triangles[*start..*end] {
+                        self.check_triangle(triangle, point, &mut nearest);
+                    }
+                }
+                BvhNode::Branch { left, right, .. } => {
+                    // visit the closer child first (it's popped last), so we can prune more.
+                    let dl = self.nodes[*left].bounds().distance(point);
+                    let dr = self.nodes[*right].bounds().distance(point);
+                    if dl < dr {
+                        stack.push(*right);
+                        stack.push(*left);
+                    } else {
+                        stack.push(*left);
+                        stack.push(*right);
+                    }
+                }
+            }
+        }
+        nearest
+    }
+
+    fn check_triangle(&self, triangle: &Triangle, point: &Vec3, nearest: &mut Nearest) {
+        let [a, b, c] = triangle.indices;
+        let (closest, feature) = closest_feature_on_triangle(
+            point, &self.vertices[a], &self.vertices[b], &self.vertices[c],
+        );
+        let distance2 = closest.dist2(point);
+        if distance2 < nearest.distance2 {
+            nearest.distance2 = distance2;
+            nearest.point = closest;
+            nearest.pseudo_normal = match feature {
+                TriangleFeature::Vertex(i) => self.vertex_normals[triangle.indices[i]].clone(),
+                TriangleFeature::Edge(i) => triangle.edge_normals[i].clone(),
+                TriangleFeature::Face => triangle.normal.clone(),
+            };
+        }
+    }
+}
+
+impl BvhNode {
+    fn bounds(&self) -> &Bounds {
+        match self {
+            BvhNode::Leaf { bounds, .. } => bounds,
+            BvhNode::Branch { bounds, .. } => bounds,
+        }
+    }
+}
+
+impl Nearest {
+    fn signed_distance(&self, point: &Vec3) -> f64 {
+        let offset = point - &self.point;
+        let distance = self.distance2.sqrt();
+        if &offset * &self.pseudo_normal < 0. {
+            -distance
+        } else {
+            distance
+        }
+    }
+}
+
+impl SDF for TriangleMesh {
+    fn distance(&self, point: &Vec3) -> f64 {
+        self.nearest(point).signed_distance(point)
+    }
+
+    fn normal(&self, point: &Vec3) -> Vec3 {
+        let nearest = self.nearest(point);
+        let distance = nearest.signed_distance(point);
+        if distance.abs() < self.epsilon / 10. {
+            return nearest.pseudo_normal;
+        }
+        (point - &nearest.point).scale(1. / distance)
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.epsilon
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        Some(self.nodes[0].bounds().clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mesh::*;
+
+    const CUBE: &str = "
+# unit cube centered on the origin
+v -0.5 -0.5 -0.5
+v  0.5 -0.5 -0.5
+v  0.5  0.5 -0.5
+v -0.5  0.5 -0.5
+v -0.5 -0.5  0.5
+v  0.5 -0.5  0.5
+v  0.5  0.5  0.5
+v -0.5  0.5  0.5
+vn 0 0 1
+f 1//1 4//1 3//1 2//1
+f 5 6 7 8
+f 1 2 6 5
+f 2 3 7 6
+f 3 4 8 7
+f -8 -4 -1 -5
+";
+
+    #[test]
+    fn obj_cube() {
+        let cube = TriangleMesh::from_obj(CUBE).unwrap();
+        assert_eq!(cube.triangles.len(), 12);
+        assert_eq!(cube.distance(&Vec3::zero()).to_string(), (-0.5).to_string());
+        assert_eq!(cube.distance(&Vec3::new(2., 0., 0.)).to_string(), 1.5.to_string());
+        assert!((cube.distance(&Vec3::new(0., 0.25, 0.4)) + 0.1).abs() < 0.0001);
+        assert!((cube.distance(&Vec3::new(1., 1., 1.)) - 0.75f64.sqrt()).abs() < 0.0001);
+        assert!((cube.distance(&Vec3::new(-1., 1., 0.)) - 0.5f64.sqrt()).abs() < 0.0001);
+        assert_eq!(cube.normal(&Vec3::new(0., -3., 0.1)).to_string(), Vec3::down().to_string());
+    }
+
+    #[test]
+    fn obj_errors() {
+        assert!(TriangleMesh::from_obj("").is_err());
+        assert!(TriangleMesh::from_obj("v 0 0 0\nv 1 0 0\nf 1 2 3").is_err());
+        assert!(TriangleMesh::from_obj("v 0 0 zero").is_err());
+    }
+}
@@ -0,0 +1,388 @@
+use crate::linear::*;
+use crate::mat::Material;
+use crate::sdf::{Displacement, SDF};
+
+/// A smooth pseudo-random field over space, with values from -1 to 1. The same seed always
+/// gives the same field.
+pub trait Noise {
+    fn sample(&self, point: &Vec3) -> f64;
+
+    /// The most the noise can change per unit of distance.
+    fn lipschitz(&self) -> f64;
+
+    /// Sums `octaves` copies of this noise, each at double the frequency and `gain` times the
+    /// amplitude of the last (fractal brownian motion).
+    fn fbm(self, octaves: usize, gain: f64) -> Octaves where Self: Sized + 'static {
+        Octaves::new(Box::new(self), octaves, gain, OctaveMode::Fbm)
+    }
+
+    /// Like `fbm`, but folds each octave into sharp ridges where it crosses zero.
+    fn ridged(self, octaves: usize, gain: f64) -> Octaves where Self: Sized + 'static {
+        Octaves::new(Box::new(self), octaves, gain, OctaveMode::Ridged)
+    }
+
+    /// Like `fbm`, but sums the absolute value of each octave, giving billowy creases.
+    fn turbulence(self, octaves: usize, gain: f64) -> Octaves where Self: Sized + 'static {
+        Octaves::new(Box::new(self), octaves, gain, OctaveMode::Turbulence)
+    }
+
+    /// Pushes surfaces out by up to `amplitude` where the noise is positive, and in where it's
+    /// negative, for use with `SDF::displace`.
+    fn displacement(self, amplitude: f64) -> NoiseDisplacement where Self: Sized + 'static {
+        NoiseDisplacement { noise: Box::new(self), amplitude }
+    }
+}
+
+/// Gradient noise (Perlin's improved noise), with features about `1 / frequency` apart.
+#[derive(Clone)]
+pub struct Perlin {
+    seed: u64,
+    frequency: f64,
+}
+
+/// Random values at each lattice point, smoothly interpolated.
+#[derive(Clone)]
+pub struct ValueNoise {
+    seed: u64,
+    frequency: f64,
+}
+
+/// Cellular noise, from -1 right on one of the randomly scattered feature points, rising with
+/// the distance to the nearest one.
+#[derive(Clone)]
+pub struct Worley {
+    seed: u64,
+    frequency: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OctaveMode {
+    Fbm,
+    Ridged,
+    Turbulence,
+}
+
+pub struct Octaves {
+    noise: Box<dyn Noise>,
+    octaves: usize,
+    gain: f64,
+    mode: OctaveMode,
+}
+
+pub struct NoiseDisplacement {
+    noise: Box<dyn Noise>,
+    amplitude: f64,
+}
+
+/// Shades a shape by blending between two materials, from `low` where the noise is -1 to
+/// `high` where it's 1.
+pub struct TexturedSDF {
+    sdf: Box<dyn SDF>,
+    noise: Box<dyn Noise>,
+    low: Material,
+    high: Material,
+}
+
+/// Steepest slope of Perlin noise at frequency 1. The most we've measured is about 3.2.
+const PERLIN_LIPSCHITZ: f64 = 4.;
+
+impl Perlin {
+    pub fn new(seed: u64, frequency: f64) -> Self {
+        Self { seed, frequency }
+    }
+}
+
+impl ValueNoise {
+    pub fn new(seed: u64, frequency: f64) -> Self {
+        Self { seed, frequency }
+    }
+}
+
+impl Worley {
+    pub fn new(seed: u64, frequency: f64) -> Self {
+        Self { seed, frequency }
+    }
+}
+
+impl Octaves {
+    pub fn new(noise: Box<dyn Noise>, octaves: usize, gain: f64, mode: OctaveMode) -> Self {
+        Self { noise, octaves: octaves.max(1), gain, mode }
+    }
+
+    fn total_amplitude(&self) -> f64 {
+        (0..self.octaves).map(|i| self.gain.abs().powi(i as i32)).sum()
+    }
+}
+
+impl TexturedSDF {
+    pub fn new(sdf: Box<dyn SDF>, noise: Box<dyn Noise>, low: Material, high: Material) -> Self {
+        Self { sdf, noise, low, high }
+    }
+}
+
+/// Reads a noise from scene arguments: `perlin|value|worley seed frequency`, optionally followed
+/// by `fbm|ridged|turbulence octaves gain`.
+pub fn parse_noise(arguments: &[&str]) -> Result<Box<dyn Noise>, String> {
+    let number = |s: &str| s.parse::<f64>().map_err(|e| format!("bad noise argument {}: {}", s, e));
+    let (kind, seed, frequency, octaves) = match arguments {
+        [kind, seed, frequency, octaves @ ..] => (*kind, *seed, number(frequency)?, octaves),
+        _ => return Err(format!("noise needs a kind, seed and frequency, got {:?}", arguments)),
+    };
+    let seed = seed.parse::<u64>().map_err(|e| format!("bad noise seed {}: {}", seed, e))?;
+    let noise: Box<dyn Noise> = match kind {
+        "perlin" => Box::new(Perlin::new(seed, frequency)),
+        "value" => Box::new(ValueNoise::new(seed, frequency)),
+        "worley" => Box::new(Worley::new(seed, frequency)),
+        _ => return Err(format!("unknown noise: {}", kind)),
+    };
+    let (mode, count, gain) = match octaves {
+        [] => return Ok(noise),
+        [mode, count, gain] => (*mode, *count, number(gain)?),
+        _ => return Err(format!("octaves need a mode, count and gain, got {:?}", octaves)),
+    };
+    let count = count.parse::<usize>().map_err(|e| format!("bad octave count {}: {}", count, e))?;
+    match mode {
+        "fbm" => Ok(Box::new(noise.fbm(count, gain))),
+        "ridged" => Ok(Box::new(noise.ridged(count, gain))),
+        "turbulence" => Ok(Box::new(noise.turbulence(count, gain))),
+        _ => Err(format!("unknown octave mode: {}", mode)),
+    }
+}
+
+/// Mixes the lattice point and seed into well-scattered bits (splitmix64's finalizer).
+fn hash(x: i64, y: i64, z: i64, seed: u64) -> u64 {
+    let mix = |mut h: u64| {
+        h = (h ^ (h >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        h = (h ^ (h >> 27)).wrapping_mul(0x94d049bb133111eb);
+        h ^ (h >> 31)
+    };
+    let mut h = mix(seed.wrapping_add(0x9e3779b97f4a7c15));
+    for &v in &[x, y, z] {
+        h = mix(h ^ (v as u64).wrapping_add(0x9e3779b97f4a7c15));
+    }
+    h
+}
+
+/// A value in [0, 1) from some of the hash's bits.
+fn unit(hash: u64, shift: u32) -> f64 {
+    ((hash >> shift) & 0xffff) as f64 / 65536.
+}
+
+/// Smooths the interpolation between lattice points so the noise has no visible grid.
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6. - 15.) + 10.)
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+/// Interpolates a value computed at each corner of the lattice cell containing the point.
+fn interpolate_cell<F: Fn(i64, i64, i64, &Vec3) -> f64>(point: &Vec3, corner: F) -> f64 {
+    let (x, y, z) = (point.x.floor(), point.y.floor(), point.z.floor());
+    let local = Vec3::new(point.x - x, point.y - y, point.z - z);
+    let (x, y, z) = (x as i64, y as i64, z as i64);
+    let value = |dx: i64, dy: i64, dz: i64| {
+        corner(x + dx, y + dy, z + dz, &Vec3::new(
+            local.x - dx as f64,
+            local.y - dy as f64,
+            local.z - dz as f64,
+        ))
+    };
+    let (u, v, w) = (fade(local.x), fade(local.y), fade(local.z));
+    let plane = |dz: i64| lerp(
+        lerp(value(0, 0, dz), value(1, 0, dz), u),
+        lerp(value(0, 1, dz), value(1, 1, dz), u),
+        v,
+    );
+    lerp(plane(0), plane(1), w)
+}
+
+impl Noise for Box<dyn Noise> {
+    fn sample(&self, point: &Vec3) -> f64 {
+        self.as_ref().sample(point)
+    }
+
+    fn lipschitz(&self) -> f64 {
+        self.as_ref().lipschitz()
+    }
+}
+
+impl Noise for Perlin {
+    fn sample(&self, point: &Vec3) -> f64 {
+        // https://mrl.cs.nyu.edu/~perlin/noise/
+        interpolate_cell(&point.clone().scale(self.frequency), |x, y, z, offset| {
+            // one of the twelve directions to the edges of a cube.
+            let h = hash(x, y, z, self.seed) % 12;
+            let u = if h < 8 { offset.x } else { offset.y };
+            let v = if h < 4 { offset.y } else if h == 8 || h == 9 { offset.x } else { offset.z };
+            (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+        }).clamp(-1., 1.)
+    }
+
+    fn lipschitz(&self) -> f64 {
+        PERLIN_LIPSCHITZ * self.frequency
+    }
+}
+
+impl Noise for ValueNoise {
+    fn sample(&self, point: &Vec3) -> f64 {
+        interpolate_cell(&point.clone().scale(self.frequency), |x, y, z, _| {
+            unit(hash(x, y, z, self.seed), 0) * 2. - 1.
+        })
+    }
+
+    fn lipschitz(&self) -> f64 {
+        // each axis's slope is at most the biggest jump between corners (2) times the
+        // steepest part of the fade (15/8).
+        2. * 15. / 8. * 3f64.sqrt() * self.frequency
+    }
+}
+
+impl Noise for Worley {
+    fn sample(&self, point: &Vec3) -> f64 {
+        let p = point.clone().scale(self.frequency);
+        let (x, y, z) = (p.x.floor() as i64, p.y.floor() as i64, p.z.floor() as i64);
+        let mut nearest = f64::INFINITY;
+        for dz in -1..=1 {
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let (cx, cy, cz) = (x + dx, y + dy, z + dz);
+                    let h = hash(cx, cy, cz, self.seed);
+                    let feature = Vec3::new(
+                        cx as f64 + unit(h, 0),
+                        cy as f64 + unit(h, 16),
+                        cz as f64 + unit(h, 32),
+                    );
+                    nearest = nearest.min(feature.dist2(&p));
+                }
+            }
+        }
+        (nearest.sqrt() * 2. - 1.).min(1.)
+    }
+
+    fn lipschitz(&self) -> f64 {
+        2. * self.frequency
+    }
+}
+
+impl Noise for Octaves {
+    fn sample(&self, point: &Vec3) -> f64 {
+        let mut sum = 0.;
+        let mut amplitude = 1.;
+        let mut p = point.clone();
+        for octave in 0..self.octaves {
+            let n = self.noise.sample(&p);
+            sum += amplitude * match self.mode {
+                OctaveMode::Fbm => n,
+                OctaveMode::Ridged => 1. - 2. * n.abs(),
+                OctaveMode::Turbulence => 2. * n.abs() - 1.,
+            };
+            amplitude *= self.gain;
+            // shift each octave a little, so their lattices don't line up at the origin.
+            p = p.scale(2.).add(1., &Vec3::new(17.3, -5.9, 31.1).scale(octave as f64 + 1.));
+        }
+        sum / self.total_amplitude()
+    }
+
+    fn lipschitz(&self) -> f64 {
+        // ridges and turbulence fold the noise, which doubles its slope.
+        let fold = if self.mode == OctaveMode::Fbm { 1. } else { 2. };
+        let slopes: f64 = (0..self.octaves).map(|i| (2. * self.gain.abs()).powi(i as i32)).sum();
+        fold * self.noise.lipschitz() * slopes / self.total_amplitude()
+    }
+}
+
+impl Displacement for NoiseDisplacement {
+    fn displacement(&self, point: &Vec3) -> f64 {
+        -self.amplitude * self.noise.sample(point)
+    }
+
+    fn lipschitz(&self) -> f64 {
+        self.amplitude.abs() * self.noise.lipschitz()
+    }
+}
+
+impl SDF for TexturedSDF {
+    fn distance(&self, point: &Vec3) -> f64 {
+        self.sdf.distance(point)
+    }
+
+    fn normal(&self, point: &Vec3) -> Vec3 {
+        self.sdf.normal(point)
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.sdf.epsilon()
+    }
+
+    fn material(&self, point: &Vec3) -> Option<Material> {
+        let s = (self.noise.sample(point) + 1.) / 2.;
+        Some(self.low.clone().lerp(s.clamp(0., 1.), &self.high))
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        self.sdf.bounds()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mat::Color;
+    use crate::noise::*;
+    use crate::sdf::Sphere;
+
+    /// The steepest slope between nearby samples along a few lines through the noise.
+    fn measured_slope(noise: &dyn Noise) -> f64 {
+        let step = 0.001;
+        let mut slope: f64 = 0.;
+        for i in 0..20_000 {
+            let t = i as f64 * 0.0173;
+            let a = Vec3::new(t, t * 0.37 + 0.5, -t * 0.71);
+            for direction in &[Vec3::right(), Vec3::up(), Vec3::new(1., 1., 1.).normalize()] {
+                let b = a.clone().add(step, direction);
+                slope = slope.max((noise.sample(&b) - noise.sample(&a)).abs() / step);
+            }
+        }
+        slope
+    }
+
+    #[test]
+    fn noise_is_seeded_and_bounded() {
+        let noises: Vec<Box<dyn Noise>> = vec![
+            Box::new(Perlin::new(7, 2.)),
+            Box::new(ValueNoise::new(7, 2.)),
+            Box::new(Worley::new(7, 2.)),
+            Box::new(Perlin::new(7, 1.).fbm(5, 0.5)),
+            Box::new(Perlin::new(7, 1.).ridged(4, 0.5)),
+            Box::new(ValueNoise::new(7, 1.).turbulence(4, 0.6)),
+        ];
+        let p = Vec3::new(0.3, -1.7, 2.2);
+        for noise in &noises {
+            let slope = measured_slope(noise.as_ref());
+            assert!(slope <= noise.lipschitz(), "slope {} over {}", slope, noise.lipschitz());
+            for i in 0..1000 {
+                let v = noise.sample(&Vec3::new(i as f64 * 0.31, i as f64 * -0.17, i as f64 * 0.07));
+                assert!((-1. ..=1.).contains(&v), "{} out of range", v);
+            }
+        }
+        assert_eq!(Perlin::new(7, 2.).sample(&p), Perlin::new(7, 2.).sample(&p));
+        assert_ne!(Perlin::new(7, 2.).sample(&p), Perlin::new(8, 2.).sample(&p));
+        assert_eq!(Perlin::new(1, 1.).sample(&Vec3::new(3., -2., 5.)), 0.);
+    }
+
+    #[test]
+    fn noise_displacement_and_texture() {
+        let lumpy = Sphere::new(1.).displace(Perlin::new(3, 4.).displacement(0.1));
+        assert!(lumpy.distance(&Vec3::new(0., 0., 1.2)) > 0.);
+        assert!(lumpy.distance(&Vec3::new(0., 0., 0.8)) < 0.);
+
+        let mut black = Material::new();
+        black.diffuse = Color::black();
+        let marble = Sphere::new(1.).textured(Worley::new(3, 4.), black, Material::new());
+        let shades: Vec<String> = (0..10)
+            .map(|i| marble.material(&Vec3::new(i as f64 * 0.1, 0., 1.)).unwrap().diffuse.to_string())
+            .collect();
+        assert!(shades.iter().any(|s| s != &shades[0]));
+    }
+}
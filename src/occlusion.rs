@@ -0,0 +1,85 @@
+use crate::linear::*;
+use crate::path::cosine_sample;
+use crate::rng::Rng;
+use crate::sdf::SDF;
+
+/// Settings for darkening ambient light in creases and corners, by checking how close other
+/// surfaces are to a point. Distance fields make this cheap: out in the open, the distance grows
+/// as fast as we step away from the surface, and anything that keeps it smaller is in the way.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AmbientOcclusion {
+    /// How many distances to check along each direction.
+    pub steps: usize,
+    /// How far out the furthest check is. Surfaces further away than this don't occlude.
+    pub distance: f64,
+    /// How dark fully occluded points get, from 0 to 1 (or more, to exaggerate it).
+    pub strength: f64,
+    /// If more than 0, this many directions are picked around the normal instead of only
+    /// stepping along it. It's noisier, but sees things off to the side.
+    pub samples: usize,
+}
+
+impl AmbientOcclusion {
+    pub fn new() -> Self {
+        Self { steps: 5, distance: 0.5, strength: 1., samples: 0 }
+    }
+
+    /// How much of the ambient light reaches the point, from 0 to 1.
+    pub fn visibility(&self, sdf: &dyn SDF, point: &Vec3, normal: &Vec3, rng: &mut Rng) -> f64 {
+        let occlusion = if self.samples == 0 {
+            self.occlusion(sdf, point, normal, normal)
+        } else {
+            (0..self.samples)
+                .map(|_| self.occlusion(sdf, point, normal, &cosine_sample(normal, rng)))
+                .sum::<f64>() / self.samples as f64
+        };
+        (1. - self.strength * occlusion).clamp(0., 1.)
+    }
+
+    /// How blocked the direction is, from 0 to 1. Closer checks count for more, since nearby
+    /// surfaces block more of the light.
+    fn occlusion(&self, sdf: &dyn SDF, point: &Vec3, normal: &Vec3, direction: &Vec3) -> f64 {
+        // a flat surface gets closer the further the direction is from the normal.
+        let rise = direction * normal;
+        let mut occluded = 0.;
+        let mut total = 0.;
+        let mut weight = 1.;
+        for step in 1..=self.steps {
+            let h = self.distance * step as f64 / self.steps as f64;
+            let open = h * rise;
+            let d = sdf.distance(&point.clone().add(h, direction));
+            occluded += weight * (open - d).clamp(0., open);
+            total += weight * open;
+            weight *= 0.5;
+        }
+        if total <= 0. {
+            return 0.;
+        }
+        occluded / total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::occlusion::*;
+    use crate::sdf::Sphere;
+
+    #[test]
+    fn corners_are_occluded() {
+        let floor = Sphere::new(100.).translate(Vec3::new(0., -100., 0.));
+        let ball = Sphere::new(1.).translate(Vec3::new(0., 1., 0.));
+        let sdf = floor.union(Box::new(ball));
+        let mut rng = Rng::new(4, 0);
+        let mut ao = AmbientOcclusion::new();
+        ao.distance = 1.;
+        for &samples in &[0, 16] {
+            ao.samples = samples;
+            let open = ao.visibility(&sdf, &Vec3::new(10., 0., 0.), &Vec3::up(), &mut rng);
+            let crease = ao.visibility(&sdf, &Vec3::new(1.2, 0., 0.), &Vec3::up(), &mut rng);
+            assert!(open > 0.99, "{}", open);
+            assert!(crease < 0.9, "{}", crease);
+        }
+        ao.strength = 0.;
+        assert_eq!(ao.visibility(&sdf, &Vec3::new(1.2, 0., 0.), &Vec3::up(), &mut rng), 1.);
+    }
+}
@@ -0,0 +1,239 @@
+use std::f64::consts::PI;
+
+use crate::linear::*;
+use crate::mat::{Color, RefractionConstants};
+use crate::rng::Rng;
+use crate::scene::{fresnel, refract, Scene};
+use crate::sdf::{NegatedRefSDF, RayHit, SDF};
+
+/// How the color seen along a ray is worked out.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Integrator {
+    /// Recursive ray tracing with hard shadows, perfect mirrors and a single refracted path.
+    Whitted,
+    PathTracer(PathTracer),
+}
+
+/// Monte Carlo path tracing. Each path picks one way to scatter at every surface it hits, so a
+/// single path is noisy, but the average over many of them converges to the full lighting,
+/// including light bouncing between diffuse surfaces.
+///
+/// It reads the same materials as the Whitted tracer: the ambient color is treated as light
+/// the surface gives off, opacity and reflectivity are the chances of a path going through or
+/// bouncing off like a mirror, and the rest of the time it scatters diffusely.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PathTracer {
+    /// Paths never bounce more than this many times.
+    pub max_depth: usize,
+    /// Paths get at least this many bounces before Russian roulette can end them early.
+    pub roulette_depth: usize,
+}
+
+/// Roulette never gives a path less than this chance of going on, so dim paths that survive
+/// aren't weighted up too much.
+const MIN_SURVIVAL: f64 = 0.05;
+
+/// How many transparent surfaces shadow rays look through before giving up.
+const MAX_SHADOW_CROSSINGS: usize = 10;
+
+impl PathTracer {
+    pub fn new(max_depth: usize) -> Self {
+        Self { max_depth, roulette_depth: 3 }
+    }
+
+    /// The light coming back along the ray that made the hit.
+    pub fn radiance(&self, scene: &Scene, hit: RayHit, rng: &mut Rng) -> Color {
+        let epsilon = scene.sdf.epsilon();
+        let mut radiance = Color::black();
+        let mut throughput = Color::white();
+        let mut hit = hit;
+        for depth in 0..self.max_depth {
+            let material = &hit.material;
+            radiance = radiance.add(1., &(&throughput * &material.ambient));
+
+            let incoming = hit.ray.direction.clone().normalize();
+            let transmission = 1. - material.opacity;
+            // pbr materials reflect through their specular lobe instead.
+            let reflection = match material.pbr {
+                Some(_) => 0.,
+                None => material.opacity * material.reflectivity,
+            };
+            let choice = rng.next_f64();
+            // each way of scattering is picked as often as it contributes, so the weights cancel.
+            let next = if choice < transmission {
+                match transmit(scene, &hit, self.max_depth, rng) {
+                    Some((ray, transmittance)) => {
+                        throughput = throughput.multiply(&transmittance);
+                        ray
+                    }
+                    None => break,
+                }
+            } else if choice < transmission + reflection {
+                Ray::new(
+                    hit.point.clone().add(epsilon * 2., &hit.normal),
+                    incoming.clone().add(-2., &incoming.clone().on_axis(&hit.normal)),
+                )
+            } else {
+                // point lights can't be hit by chance, so we look at them directly instead.
+                let direct = direct_light(scene, &hit, rng);
+                radiance = radiance.add(1., &(&throughput * &direct));
+                let direction = match &material.pbr {
+                    Some(pbr) => {
+                        let v = incoming.clone().scale(-1.);
+                        match pbr.sample(&hit.normal, &v, rng) {
+                            Some((direction, weight)) => {
+                                throughput = throughput.multiply(&weight);
+                                direction
+                            }
+                            None => break,
+                        }
+                    }
+                    None => {
+                        throughput = throughput.multiply(&material.diffuse);
+                        cosine_sample(&hit.normal, rng)
+                    }
+                };
+                Ray::new(hit.point.clone().add(epsilon * 2., &hit.normal), direction)
+            };
+
+            if depth + 1 >= self.roulette_depth {
+                let (r, g, b): (f64, f64, f64) = (&throughput).into();
+                let survival = r.max(g).max(b).clamp(MIN_SURVIVAL, 1.);
+                if rng.next_f64() >= survival {
+                    break;
+                }
+                throughput = throughput.scale(1. / survival);
+            }
+
+            hit = match scene.sdf.raymarch(&next, scene.far_plane) {
+                Some(hit) => hit,
+                None => {
+                    // the path got away, into whatever ambient light there is.
+                    radiance = radiance.add(1., &(&throughput * &scene.background(&next.direction)));
+                    break;
+                }
+            };
+        }
+        radiance
+    }
+}
+
+/// Diffuse and specular light reaching the hit from every light it can see. Lights are as
+/// bright as they are in the Whitted tracer, so the two agree on directly lit scenes.
+fn direct_light(scene: &Scene, hit: &RayHit, rng: &mut Rng) -> Color {
+    let material = &hit.material;
+    // far enough out that marching doesn't stop right away on the surface we started from.
+    let adjusted_hit = hit.point.clone().add(scene.sdf.epsilon() * 2., &hit.normal);
+    let v = hit.ray.direction.clone().normalize().scale(-1.);
+    let mut color = Color::black();
+    for light in &scene.lights {
+        let (ld, lc) = scene.light_sample(light, &adjusted_hit, MAX_SHADOW_CROSSINGS, rng);
+        let diffuse_strength = &ld * &hit.normal;
+        if diffuse_strength <= 0. || lc.is_black() {
+            continue;
+        }
+        if let Some(pbr) = &material.pbr {
+            color = color.add(PI, &pbr.evaluate(&hit.normal, &v, &ld).multiply(&lc));
+            continue;
+        }
+        let lr = ld.clone().add(-2., &ld.clone().off_axis(&hit.normal));
+        let specular_strength = (&lr * &v).max(0.).powf(material.phong);
+        color = color
+            .add(diffuse_strength, &(&material.diffuse * &lc))
+            .add(specular_strength, &(&material.specular * &lc));
+    }
+    color
+}
+
+/// Where a path that chose to go through a transparent surface goes next, and how much of the
+/// light the inside lets through on the way. Like light, it can be reflected at the surface
+/// instead, by the fresnel equations, and once inside it keeps reflecting off the inside of
+/// the surface until it gets out.
+fn transmit(scene: &Scene, hit: &RayHit, max_bounces: usize, rng: &mut Rng) -> Option<(Ray, Color)> {
+    let epsilon = scene.sdf.epsilon();
+    let ior = hit.material.index_of_refraction;
+    let incoming = hit.ray.direction.clone().normalize();
+    let reflected = |point: &Vec3, direction: &Vec3, normal: &Vec3| Ray::new(
+        point.clone().add(epsilon * 2., normal),
+        direction.clone().add(-2., &direction.clone().on_axis(normal)),
+    );
+
+    let entering = refract(&incoming, &hit.normal, RefractionConstants::VACUUM, ior);
+    let reflectance = fresnel(&incoming * &hit.normal, RefractionConstants::VACUUM, ior);
+    let mut ray = match entering {
+        Some(direction) if rng.next_f64() >= reflectance => {
+            Ray::new(hit.point.clone().add(-epsilon * 2., &hit.normal), direction)
+        }
+        _ => return Some((reflected(&hit.point, &incoming, &hit.normal), Color::white())),
+    };
+
+    let inverse_sdf = NegatedRefSDF::new(&scene.sdf);
+    let mut transmittance = Color::white();
+    for _ in 0..max_bounces {
+        // the far side's normal points back into the shape.
+        let exit = inverse_sdf.raymarch(&ray, scene.far_plane)?;
+        transmittance = transmittance.multiply(&hit.material.transmittance(exit.point.dist(&ray.origin)));
+        let direction = exit.ray.direction.clone().normalize();
+        let reflectance = fresnel(&direction * &exit.normal, ior, RefractionConstants::VACUUM);
+        match refract(&direction, &exit.normal, ior, RefractionConstants::VACUUM) {
+            Some(out) if rng.next_f64() >= reflectance => {
+                let ray = Ray::new(exit.point.clone().add(-epsilon * 2., &exit.normal), out);
+                return Some((ray, transmittance));
+            }
+            _ => ray = reflected(&exit.point, &direction, &exit.normal),
+        }
+    }
+    None
+}
+
+/// A random direction in the hemisphere around the normal, more likely the closer it is to
+/// the normal (by the cosine of the angle), which cancels out Lambert's cosine law.
+pub fn cosine_sample(normal: &Vec3, rng: &mut Rng) -> Vec3 {
+    let r = rng.next_f64().sqrt();
+    let theta = 2. * PI * rng.next_f64();
+    let local = Vec3::new(r * theta.cos(), r * theta.sin(), (1. - r * r).max(0.).sqrt());
+    Basis::around(normal).project(&local)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::path::*;
+
+    #[test]
+    fn path_traced_direct_light_matches_whitted() {
+        let render = |integrator: &str, seed| {
+            let mut scene = Scene::parse(format!(
+                "fov 60\nintegrator {}\nlight 0 5 5 1 1 1\nsurface .8 .4 .2  0 0 0  0 0 0  1 0\nsphere 1 0 0 -4",
+                integrator,
+            ).lines());
+            scene.seed = seed;
+            (0..25).map(|i| scene.raycast_pixel((i % 5, i / 5), 5, 5, 0)).collect::<Vec<_>>()
+        };
+        // with nothing else for light to bounce off, every path just sees the light.
+        let whitted = render("whitted", 0);
+        let traced = render("path 4", 0);
+        assert!(whitted[12].is_some());
+        for (a, b) in whitted.iter().zip(&traced) {
+            assert_eq!(a.is_some(), b.is_some());
+            if let (Some(a), Some(b)) = (a, b) {
+                let (dr, dg, db): (f64, f64, f64) = (&(a - b)).into();
+                assert!(dr.abs().max(dg.abs()).max(db.abs()) < 0.01, "{} vs {}", a, b);
+            }
+        }
+        assert!(traced == render("path 4", 0));
+    }
+
+    #[test]
+    fn cosine_weighted_directions() {
+        let mut rng = Rng::new(3, 0);
+        let normal = Vec3::new(1., 1., 0.).normalize();
+        let n = 20_000;
+        // the average cosine under a cosine-weighted distribution is 2/3.
+        let mean = (0..n).map(|_| {
+            let d = cosine_sample(&normal, &mut rng);
+            assert!((d.norm() - 1.).abs() < 1e-9 && &d * &normal >= 0.);
+            &d * &normal
+        }).sum::<f64>() / n as f64;
+        assert!((mean - 2. / 3.).abs() < 0.01, "{}", mean);
+    }
+}
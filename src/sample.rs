@@ -0,0 +1,405 @@
+use crate::linear::Vec3;
+use crate::mat::{Color, Material};
+use crate::rng::Rng;
+
+/// Where in a pixel's footprint the samples go.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SamplePattern {
+    /// A regular grid. Rounds the count up to a square.
+    Grid,
+    /// A grid tilted so no two samples share a row or column, which handles near-vertical
+    /// and near-horizontal edges better. Rounds the count up to a square.
+    RotatedGrid,
+    /// One random sample in each cell of a grid. Rounds the count up to a square.
+    Stratified,
+    /// The Halton sequence in bases 2 and 3, randomly shifted for each pixel.
+    Halton,
+    /// The first two dimensions of the Sobol sequence, randomly scrambled for each pixel.
+    Sobol,
+}
+
+/// How much each sample counts towards the pixel, by its offset from the pixel's center.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    Box,
+    Tent,
+    Gaussian,
+    /// Mitchell-Netravali with B = C = 1/3. It's sharper than the others, but it has negative
+    /// lobes, so it can ring around hard edges.
+    Mitchell,
+}
+
+/// Settings for supersampling each pixel.
+#[derive(Clone, Debug)]
+pub struct Sampler {
+    pub samples: usize,
+    pub pattern: SamplePattern,
+    pub filter: Filter,
+    /// If set, pixels get one sample first, and only the ones that differ from a neighbor by
+    /// more than this much (in any color channel) get the rest.
+    pub adaptive: Option<f64>,
+}
+
+/// What one ray through the center of a pixel saw. Shapes don't have ids, so the material,
+/// normal and depth of the hit stand in for which object it was.
+#[derive(Clone, Debug)]
+pub struct PixelProbe {
+    pub color: Option<Color>,
+    pub material: Option<Material>,
+    pub normal: Option<Vec3>,
+    pub depth: Option<f64>,
+}
+
+/// Neighboring normals closer than this (as a cosine, about 25°) are on the same smooth surface.
+const SMOOTH_NORMALS: f64 = 0.9;
+
+/// Neighbors whose depths differ by more than this fraction are on different surfaces.
+const DEPTH_JUMP: f64 = 0.1;
+
+/// How far a gaussian falls off; it's cut off at the filter's radius.
+const GAUSSIAN_FALLOFF: f64 = 2.;
+
+impl SamplePattern {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "grid" => Ok(SamplePattern::Grid),
+            "rotated" => Ok(SamplePattern::RotatedGrid),
+            "stratified" => Ok(SamplePattern::Stratified),
+            "halton" => Ok(SamplePattern::Halton),
+            "sobol" => Ok(SamplePattern::Sobol),
+            _ => Err(format!("unknown sample pattern: {}", name)),
+        }
+    }
+
+    /// Points in the unit square.
+    pub fn points(&self, count: usize, rng: &mut Rng) -> Vec<(f64, f64)> {
+        let count = count.max(1);
+        let side = (count as f64).sqrt().ceil() as usize;
+        let cells = (0..side * side).map(|i| ((i % side) as f64, (i / side) as f64));
+        let side = side as f64;
+        match self {
+            SamplePattern::Grid => cells.map(|(x, y)| ((x + 0.5) / side, (y + 0.5) / side)).collect(),
+            SamplePattern::RotatedGrid => {
+                // rotating by atan(1/2) and wrapping around puts every sample in its own row and
+                // column, when there are n^2 of them on an n^2 by n^2 grid.
+                let (sin, cos) = (0.5f64).atan().sin_cos();
+                cells.map(|(x, y)| {
+                    let (x, y) = ((x + 0.5) / side - 0.5, (y + 0.5) / side - 0.5);
+                    ((x * cos - y * sin + 0.5).rem_euclid(1.), (x * sin + y * cos + 0.5).rem_euclid(1.))
+                }).collect()
+            }
+            SamplePattern::Stratified => cells
+                .map(|(x, y)| ((x + rng.next_f64()) / side, (y + rng.next_f64()) / side))
+                .collect(),
+            SamplePattern::Halton => {
+                let shift = (rng.next_f64(), rng.next_f64());
+                (1..=count as u64)
+                    .map(|i| ((radical_inverse(i, 2) + shift.0) % 1., (radical_inverse(i, 3) + shift.1) % 1.))
+                    .collect()
+            }
+            SamplePattern::Sobol => {
+                let scramble = (rng.next_u32(), rng.next_u32());
+                (0..count as u32)
+                    .map(|i| (to_unit(i.reverse_bits() ^ scramble.0), to_unit(sobol2(i) ^ scramble.1)))
+                    .collect()
+            }
+        }
+    }
+}
+
+impl Filter {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "box" => Ok(Filter::Box),
+            "tent" => Ok(Filter::Tent),
+            "gaussian" => Ok(Filter::Gaussian),
+            "mitchell" => Ok(Filter::Mitchell),
+            _ => Err(format!("unknown filter: {}", name)),
+        }
+    }
+
+    /// How far from the pixel's center (in pixels) samples still count.
+    pub fn radius(&self) -> f64 {
+        match self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.,
+            Filter::Gaussian => 1.5,
+            Filter::Mitchell => 2.,
+        }
+    }
+
+    /// The weight of a sample this far from the pixel's center. Filters are separable, so this
+    /// is the product of the weights along each axis.
+    pub fn weight(&self, offset: (f64, f64)) -> f64 {
+        self.weight_1d(offset.0) * self.weight_1d(offset.1)
+    }
+
+    fn weight_1d(&self, x: f64) -> f64 {
+        let x = x.abs();
+        let radius = self.radius();
+        if x > radius {
+            return 0.;
+        }
+        match self {
+            Filter::Box => 1.,
+            Filter::Tent => 1. - x,
+            Filter::Gaussian => (-GAUSSIAN_FALLOFF * x * x).exp() - (-GAUSSIAN_FALLOFF * radius * radius).exp(),
+            Filter::Mitchell => {
+                let (b, c) = (1. / 3., 1. / 3.);
+                let w = if x < 1. {
+                    (12. - 9. * b - 6. * c) * x * x * x + (-18. + 12. * b + 6. * c) * x * x + (6. - 2. * b)
+                } else {
+                    (-b - 6. * c) * x * x * x + (6. * b + 30. * c) * x * x + (-12. * b - 48. * c) * x
+                        + (8. * b + 24. * c)
+                };
+                w / 6.
+            }
+        }
+    }
+}
+
+impl Sampler {
+    pub fn new() -> Self {
+        Self { samples: 1, pattern: SamplePattern::Grid, filter: Filter::Box, adaptive: None }
+    }
+
+    /// Offsets from the pixel's center, in pixels, covering the filter's footprint.
+    pub fn offsets(&self, rng: &mut Rng) -> Vec<(f64, f64)> {
+        if self.samples <= 1 {
+            return vec![(0., 0.)];
+        }
+        let radius = self.filter.radius();
+        self.pattern.points(self.samples, rng)
+            .into_iter()
+            .map(|(x, y)| ((x * 2. - 1.) * radius, (y * 2. - 1.) * radius))
+            .collect()
+    }
+
+    /// A random shift for a whole pass's samples, up to half the space between them either
+    /// way, so passes after the first look at different parts of the pixel.
+    pub fn jitter(&self, rng: &mut Rng) -> (f64, f64) {
+        let spacing = if self.samples <= 1 {
+            1.
+        } else {
+            2. * self.filter.radius() / (self.samples as f64).sqrt().ceil()
+        };
+        (spacing * (rng.next_f64() - 0.5), spacing * (rng.next_f64() - 0.5))
+    }
+
+    /// The weighted average of the samples. Samples that missed everything count as black,
+    /// but if they all missed, there's nothing to show.
+    pub fn reconstruct(&self, samples: Vec<((f64, f64), Option<Color>)>) -> Option<Color> {
+        if samples.iter().all(|(_, color)| color.is_none()) {
+            return None;
+        }
+        let mut total = Color::black();
+        let mut total_weight = 0.;
+        for (offset, color) in &samples {
+            let weight = self.filter.weight(*offset);
+            if let Some(color) = color {
+                total = total.add(weight, color);
+            }
+            total_weight += weight;
+        }
+        if total_weight.abs() < 1e-12 {
+            // jitter can push every sample out of the filter's footprint, so then they all
+            // count the same instead.
+            let total = samples.iter()
+                .filter_map(|(_, color)| color.as_ref())
+                .fold(Color::black(), |total, color| total.add(1., color));
+            return Some(total.scale(1. / samples.len() as f64));
+        }
+        Some(total.scale(1. / total_weight))
+    }
+
+    /// The indices of pixels (row by row) worth sampling more: those that differ from the
+    /// pixel next to or below them by more than the threshold, or hit something else. Both
+    /// pixels on either side of an edge are refined.
+    pub fn refinements(&self, probes: &[PixelProbe], width: usize) -> Vec<usize> {
+        let threshold = match self.adaptive {
+            Some(threshold) if self.samples > 1 => threshold,
+            _ => return vec![],
+        };
+        let differ = |a: &PixelProbe, b: &PixelProbe| {
+            match (&a.material, &b.material) {
+                (Some(ma), Some(mb)) if materials_differ(ma, mb, threshold) => return true,
+                (Some(_), None) | (None, Some(_)) => return true,
+                _ => (),
+            }
+            if let (Some(na), Some(nb)) = (&a.normal, &b.normal) {
+                if na * nb < SMOOTH_NORMALS {
+                    return true;
+                }
+            }
+            if let (Some(da), Some(db)) = (a.depth, b.depth) {
+                if (da - db).abs() > DEPTH_JUMP * da.min(db) {
+                    return true;
+                }
+            }
+            let a = a.color.clone().unwrap_or_else(Color::black);
+            let b = b.color.clone().unwrap_or_else(Color::black);
+            color_difference(&a, &b) > threshold
+        };
+        let mut refine = vec![false; probes.len()];
+        for i in 0..probes.len() {
+            let neighbors = [
+                if (i + 1) % width != 0 { Some(i + 1) } else { None },
+                Some(i + width).filter(|&j| j < probes.len()),
+            ];
+            for j in neighbors.iter().flatten() {
+                if differ(&probes[i], &probes[*j]) {
+                    refine[i] = true;
+                    refine[*j] = true;
+                }
+            }
+        }
+        (0..probes.len()).filter(|&i| refine[i]).collect()
+    }
+}
+
+/// The biggest difference between the colors in any channel.
+fn color_difference(a: &Color, b: &Color) -> f64 {
+    let (r, g, b): (f64, f64, f64) = (&(a - b)).into();
+    r.abs().max(g.abs()).max(b.abs())
+}
+
+/// Whether two materials look different, as opposed to being neighboring points on a textured
+/// or blended surface whose material changes gradually.
+fn materials_differ(a: &Material, b: &Material, threshold: f64) -> bool {
+    color_difference(&a.ambient, &b.ambient) > threshold
+        || color_difference(&a.diffuse, &b.diffuse) > threshold
+        || color_difference(&a.specular, &b.specular) > threshold
+        || (a.opacity - b.opacity).abs() > threshold
+        || (a.reflectivity - b.reflectivity).abs() > threshold
+}
+
+/// The digits of `i` in `base`, mirrored around the decimal point.
+fn radical_inverse(mut i: u64, base: u64) -> f64 {
+    let mut result = 0.;
+    let mut scale = 1. / base as f64;
+    while i > 0 {
+        result += (i % base) as f64 * scale;
+        i /= base;
+        scale /= base as f64;
+    }
+    result
+}
+
+/// The second dimension of the Sobol sequence, whose direction numbers come from the
+/// polynomial x + 1.
+fn sobol2(mut i: u32) -> u32 {
+    let mut direction = 1u32 << 31;
+    let mut result = 0;
+    while i > 0 {
+        if i & 1 == 1 {
+            result ^= direction;
+        }
+        direction ^= direction >> 1;
+        i >>= 1;
+    }
+    result
+}
+
+fn to_unit(bits: u32) -> f64 {
+    bits as f64 / (1u64 << 32) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sample::*;
+
+    #[test]
+    fn patterns_are_stratified() {
+        let mut rng = Rng::new(1, 2);
+        for pattern in &[SamplePattern::Grid, SamplePattern::RotatedGrid, SamplePattern::Stratified,
+                         SamplePattern::Halton, SamplePattern::Sobol] {
+            let points = pattern.points(16, &mut rng);
+            assert_eq!(points.len(), 16);
+            assert!(points.iter().all(|(x, y)| (0. ..1.).contains(x) && (0. ..1.).contains(y)));
+            // every pattern puts one sample in each quarter of the pixel's width.
+            let mut columns: Vec<usize> = points.iter().map(|(x, _)| (x * 4.) as usize).collect();
+            columns.sort_unstable();
+            columns.dedup();
+            assert_eq!(columns.len(), 4, "{:?}", pattern);
+        }
+        // the rotated grid never lines up two samples.
+        let mut rows: Vec<usize> = SamplePattern::RotatedGrid.points(4, &mut rng).iter()
+            .map(|(_, y)| (y * 4.) as usize)
+            .collect();
+        rows.sort_unstable();
+        rows.dedup();
+        assert_eq!(rows.len(), 4);
+        assert_eq!(SamplePattern::Grid.points(5, &mut rng).len(), 9);
+        assert_eq!(radical_inverse(6, 2), 0.375);
+        assert!(SamplePattern::parse("poisson").is_err());
+    }
+
+    #[test]
+    fn filter_reconstruction() {
+        assert_eq!(Filter::Box.weight((0.4, -0.4)), 1.);
+        assert_eq!(Filter::Box.weight((0.6, 0.)), 0.);
+        assert_eq!(Filter::Tent.weight((0.5, 0.5)), 0.25);
+        assert!(Filter::Gaussian.weight((1.5, 0.)).abs() < 1e-12);
+        assert!(Filter::Mitchell.weight((1.5, 0.)) < 0.);
+        assert!((Filter::Mitchell.weight((0., 0.)) - 64. / 81.).abs() < 1e-12);
+
+        // half the samples hit white, so the edge pixel comes out half gray.
+        let sampler = Sampler { samples: 4, pattern: SamplePattern::Grid, filter: Filter::Box, adaptive: None };
+        let offsets = sampler.offsets(&mut Rng::new(0, 0));
+        let samples = offsets.into_iter()
+            .map(|offset| (offset, if offset.0 < 0. { Some(Color::white()) } else { None }))
+            .collect();
+        assert_eq!(sampler.reconstruct(samples), Some(Color::new(0.5, 0.5, 0.5)));
+        assert_eq!(sampler.reconstruct(vec![((0., 0.), None)]), None);
+
+        // jittered samples that all landed outside the box still count.
+        let outside = vec![((0.7, 0.), Some(Color::white())), ((0., -0.6), Some(Color::white())), ((0.8, 0.8), None)];
+        assert_eq!(sampler.reconstruct(outside), Some(Color::new(2. / 3., 2. / 3., 2. / 3.)));
+    }
+
+    #[test]
+    fn adaptive_refinement() {
+        let mut sampler = Sampler::new();
+        sampler.samples = 4;
+        sampler.adaptive = Some(0.1);
+        let probe = |color: Option<Color>| PixelProbe {
+            material: color.as_ref().map(|_| Material::new()),
+            normal: color.as_ref().map(|_| Vec3::backward()),
+            depth: color.as_ref().map(|_| 4.),
+            color,
+        };
+        // a 3x2 image with a vertical edge between the first and second columns, and a faint
+        // gradient along the rest that shouldn't count.
+        let probes = vec![
+            probe(None), probe(Some(Color::new(0.5, 0.5, 0.5))), probe(Some(Color::new(0.55, 0.5, 0.5))),
+            probe(None), probe(Some(Color::new(0.5, 0.5, 0.5))), probe(Some(Color::new(0.55, 0.5, 0.5))),
+        ];
+        assert_eq!(sampler.refinements(&probes, 3), vec![0, 1, 3, 4]);
+        sampler.adaptive = None;
+        assert!(sampler.refinements(&probes, 3).is_empty());
+    }
+
+    #[test]
+    fn textured_surfaces_arent_edges() {
+        let mut sampler = Sampler::new();
+        sampler.samples = 4;
+        sampler.adaptive = Some(0.1);
+        // a row across a surface whose material blends from red to blue, then a pixel that
+        // looks the same but is much further away.
+        let probe = |i: usize| {
+            let t = i as f64 * 0.02;
+            let mut material = Material::new();
+            material.diffuse = Color::new(1. - t, 0., t);
+            PixelProbe {
+                color: Some(material.diffuse.clone()),
+                material: Some(material),
+                normal: Some(Vec3::new(0., t, -1.).normalize()),
+                depth: Some(4. + t),
+            }
+        };
+        let mut probes = (0..8).map(probe).collect::<Vec<_>>();
+        assert!(sampler.refinements(&probes, 8).is_empty());
+        probes[7].depth = Some(10.);
+        assert_eq!(sampler.refinements(&probes, 8), vec![6, 7]);
+    }
+}
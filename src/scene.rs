@@ -1,12 +1,29 @@
+use std::collections::HashMap;
 use std::iter::Iterator;
 
 use regex::Regex;
 use wasm_bindgen::__rt::core::f64::consts::PI;
 use wasm_bindgen::prelude::*;
 
+use crate::curve::CurveTube;
+use crate::fractal::{JuliaSet, Mandelbulb, MengerSponge, SierpinskiTetrahedron, TrapPalette};
+use crate::grid::{Interpolation, VoxelGrid};
+use crate::light::{Light, LightShape};
 use crate::linear::*;
-use crate::mat::{Color, Material, RefractionConstants};
-use crate::sdf::{EmptySDF, RayHit, SDF, Sphere, UnionSDF, PolyFace, NegatedRefSDF};
+use crate::mat::{Color, Material, Pbr, RefractionConstants};
+use crate::mesh::TriangleMesh;
+use crate::noise::{parse_noise, Noise};
+use crate::occlusion::AmbientOcclusion;
+use crate::path::{Integrator, PathTracer};
+use crate::rng::Rng;
+use crate::sample::{Filter, PixelProbe, SamplePattern, Sampler};
+use crate::sdf::{
+    Capsule, Cone, Cuboid, Cylinder, Ellipsoid, EmptySDF, HexPrism, NegatedRefSDF, Octahedron,
+    PolyFace, RayHit, RoundedCuboid, SDF, SmoothUnionType, Sphere, Torus, UnionSDF,
+};
+use crate::sdf2::{Circle, Rect, SDF2};
+use crate::terrain::Heightfield;
+use crate::text::Font;
 
 #[wasm_bindgen]
 extern "C" {
@@ -14,32 +31,17 @@ extern "C" {
     // `log(..)`
     #[wasm_bindgen(js_namespace = console)]
     fn log(s: &str);
-
-    #[wasm_bindgen(js_namespace = Math)]
-    fn random() -> f64;
 }
 
-pub struct Light {
-    pub position: Vec3,
-    pub color: Color,
-    pub atten: f64,
-}
+/// Light bouncing around inside a transparent shape stops being followed once this little of it
+/// is left.
+const MIN_INTERIOR_WEIGHT: f64 = 0.01;
 
-impl Light {
-    pub fn new(position: Vec3, color: Color, atten: f64) -> Self {
-        Self { position, color, atten }
-    }
-
-    pub fn shadow_ray(&self, point: &Vec3) -> Ray {
-        Ray::new(point.clone(), &self.position - point)
-    }
+/// Passes to take over scenes where only the spot in the pixel changes between them.
+const JITTERED_PASSES: u64 = 16;
 
-    pub fn color(&self, point: &Vec3) -> Color {
-        let dist2 = point.dist2(&self.position);
-        let atten = ((self.atten * self.atten) / dist2).min(1.0);
-        self.color.clone().scale(atten)
-    }
-}
+/// Passes to take over scenes with random lighting, before calling it done.
+const NOISY_PASSES: u64 = 1024;
 
 pub struct Scene {
     pub sdf: Box<dyn SDF>,
@@ -47,6 +49,15 @@ pub struct Scene {
     pub view: ViewTransform,
     pub far_plane: f64,
     pub debugging: bool,
+    /// Renders with the same seed come out exactly the same.
+    pub seed: u64,
+    pub sampler: Sampler,
+    pub integrator: Integrator,
+    /// If set, shadows are softened with the SDF's penumbra estimate (see `SDF::soft_shadow`)
+    /// using this sharpness, instead of sampling points on the lights.
+    pub penumbra: Option<f64>,
+    /// If set, ambient light is darkened where other surfaces are close by.
+    pub occlusion: Option<AmbientOcclusion>,
 }
 
 pub struct OrthoView {
@@ -97,38 +108,89 @@ impl ViewTransform {
 }
 
 impl Scene {
-    pub fn raycast_pixel(&self, pixel: (usize, usize), width: usize, height: usize) -> Option<Color> {
-        let x = pixel.0 as f64;
-        let y = pixel.1 as f64;
+    /// `sample` picks which of the pixel's random streams to use, so repeated samples of the
+    /// same pixel differ, but each one is reproducible.
+    pub fn raycast_pixel(&self, pixel: (usize, usize), width: usize, height: usize, sample: u64) -> Option<Color> {
+        let mut rng = Rng::for_sample(self.seed, pixel, sample);
+        // the first pass looks where it always does; later ones fill in around it.
+        let jitter = if sample == 0 { (0., 0.) } else { self.sampler.jitter(&mut rng) };
+        let samples = self.sampler.offsets(&mut rng).into_iter().map(|offset| {
+            let offset = (offset.0 + jitter.0, offset.1 + jitter.1);
+            let ray = self.pixel_ray(pixel, offset, width, height);
+            let hit = self.sdf.raymarch(&ray, self.far_plane);
+            (offset, hit.map(|hit| self.shade(hit, &mut rng)))
+        }).collect();
+        self.sampler.reconstruct(samples)
+    }
+
+    /// Casts a single ray through the pixel, for pixels adaptive sampling decided don't need
+    /// the full set of samples. The first pass goes through the center, and later ones land
+    /// anywhere in the pixel, so they add up to a box filtered average.
+    pub fn sample_pixel(&self, pixel: (usize, usize), width: usize, height: usize, sample: u64) -> Option<Color> {
+        let mut rng = Rng::for_sample(self.seed, pixel, sample);
+        let offset = if sample == 0 { (0., 0.) } else { (rng.next_f64() - 0.5, rng.next_f64() - 0.5) };
+        let ray = self.pixel_ray(pixel, offset, width, height);
+        self.sdf.raymarch(&ray, self.far_plane).map(|hit| self.shade(hit, &mut rng))
+    }
+
+    /// Casts a single ray through the center of the pixel, for adaptive sampling to decide
+    /// whether it's worth refining.
+    pub fn probe_pixel(&self, pixel: (usize, usize), width: usize, height: usize, sample: u64) -> PixelProbe {
+        let mut rng = Rng::for_sample(self.seed, pixel, sample);
+        let ray = self.pixel_ray(pixel, (0., 0.), width, height);
+        match self.sdf.raymarch(&ray, self.far_plane) {
+            Some(hit) => PixelProbe {
+                material: Some(hit.material.clone()),
+                normal: Some(hit.normal.clone()),
+                depth: Some(hit.point.dist(&hit.ray.origin)),
+                color: Some(self.shade(hit, &mut rng)),
+            },
+            None => PixelProbe { color: None, material: None, normal: None, depth: None },
+        }
+    }
+
+    /// The ray through a point in the pixel, `offset` pixels from its center.
+    fn pixel_ray(&self, pixel: (usize, usize), offset: (f64, f64), width: usize, height: usize) -> Ray {
         let width = width as f64;
         let height = height as f64;
+        let x = pixel.0 as f64 + 0.5 + offset.0;
+        let y = pixel.1 as f64 + 0.5 + offset.1;
         let x = (x - width / 2.) / (width / 2.);
         let y = (height / 2. - y) / (height / 2.);
-        let local = Vec3::new(x, y, 0.);
-        let ray = self.view.project(&local);
-        self.raycast(ray, 10)
+        self.view.project(&Vec3::new(x, y, 0.))
+    }
+
+    /// How many passes over the image are worth taking. Each pass looks at a slightly different
+    /// spot in every pixel, which antialiases edges in a few passes, but scenes that pick things
+    /// randomly stay noisy for much longer.
+    pub fn passes(&self) -> u64 {
+        let noisy = matches!(self.integrator, Integrator::PathTracer(_))
+            || (self.penumbra.is_none() && self.lights.iter().any(|light| !matches!(light.shape, LightShape::Point)))
+            || self.occlusion.as_ref().is_some_and(|occlusion| occlusion.samples > 0);
+        if noisy { NOISY_PASSES } else { JITTERED_PASSES }
+    }
+
+    /// The color of a camera ray's hit, worked out by the scene's integrator.
+    fn shade(&self, hit: RayHit, rng: &mut Rng) -> Color {
+        match &self.integrator {
+            Integrator::Whitted => self.get_color(&hit, 10, rng),
+            Integrator::PathTracer(tracer) => tracer.radiance(self, hit, rng),
+        }
     }
 
-    fn raycast(&self, ray: Ray, refl_count: usize) -> Option<Color> {
+    fn raycast(&self, ray: Ray, refl_count: usize, rng: &mut Rng) -> Option<Color> {
         if ray.origin.is_nan() || ray.direction.is_nan() {
             panic!("Cannot raycast with nan ray: {:#?}", ray);
         }
-        let ray_count = 1;
-        let mut color = None;
-        for _ in 0..ray_count {
-            let hit = self.sdf.raymarch(&perturb(&ray, 0.01), self.far_plane);
-            if let Some(col) = hit.map(|hit| self.get_color(&hit, refl_count)) {
-                color = color.map(|c| &c + &col).or(Some(col))
-            }
-        }
-        color.map(|c| c.scale(1. / (ray_count as f64)))
+        let hit = self.sdf.raymarch(&ray, self.far_plane);
+        hit.map(|hit| self.get_color(&hit, refl_count, rng))
     }
 
-    fn get_color(&self, hit: &RayHit, refl_count: usize) -> Color {
+    fn get_color(&self, hit: &RayHit, refl_count: usize, rng: &mut Rng) -> Color {
         if hit.point.is_nan() {
             panic!("Cannot get color for an NaN point! {:#?}", hit);
         }
-        let mut color = hit.material.ambient.clone().scale(hit.material.opacity);
+        let mut color = Color::black();
 
         // ray pointing toward eye
         let v = hit.ray.direction.clone().normalize().scale(-1.);
@@ -142,56 +204,36 @@ impl Scene {
             log(&format!("adjusted_hit: {}", adjusted_hit));
         }
 
+        // the part of the light that scatters evenly, which is all ambient light can reach.
+        let albedo = match &hit.material.pbr {
+            Some(pbr) => pbr.base_color.clone().scale(1. - pbr.metallic),
+            None => hit.material.diffuse.clone(),
+        };
+
+        let mut ambient = hit.material.ambient.clone();
         for light in &self.lights {
-            let lc = light.color(&hit.point);
-            let mut shadow_ray = light.shadow_ray(&adjusted_hit);
-            let ld = shadow_ray.direction.clone().normalize();
+            if let Some(sky) = light.ambient(&hit.normal) {
+                ambient = ambient.add(1., &(&albedo * &sky));
+                continue;
+            }
+
+            let (ld, lc) = self.light_sample(light, &adjusted_hit, refl_count, rng);
 
             if self.debugging {
                 log(&format!("lc {} ld {}", lc, ld));
-                log(&format!("shadow ray {}", shadow_ray));
             }
 
-            let mut light_filter = Color::white();
-
-            for _ in 0..refl_count {
-                let shadow_dir = shadow_ray.direction.clone().normalize();
-                let hit = self.sdf.raymarch(
-                    &perturb(&shadow_ray, 0.),
-                    shadow_ray.direction.norm(),
-                );
-                if hit.is_none() {
-                    break;
-                }
-                let hit = hit.unwrap();
-                if &hit.normal * &shadow_ray.direction < 0. {
-                    if hit.material.opacity < 1.0 {
-                        light_filter = light_filter.lerp(hit.material.opacity, &hit.material.diffuse);
-                        shadow_ray = light.shadow_ray(&hit.point);
-                        shadow_ray.origin = shadow_ray.origin.add(self.sdf.epsilon() * 2., &shadow_dir);
-                        let inverse_sdf = NegatedRefSDF::new(&self.sdf);
-                        // NB: this doesn't take refraction into account. not sure if I actually can
-                        // do that with this lighting method; might have to do some kind of fancy
-                        // photon simulation thing.
-                        let refr_hit = inverse_sdf.raymarch(&shadow_ray, shadow_ray.direction.norm());
-                        if refr_hit.is_none() {
-                            break;
-                        }
-                        let refr_hit = refr_hit.unwrap();
-                        shadow_ray.origin = refr_hit.point.clone().add(self.sdf.epsilon() * 2., &shadow_dir);
-                    } else {
-                        light_filter = Color::black();
-                    }
-                    break;
-                }
-                shadow_ray.origin = hit.point.clone()
-                    .add(self.sdf.epsilon() * 2., &shadow_dir);
+            if lc.is_black() {
+                continue; // fully in shadow.
             }
 
-            if light_filter.is_black() {
-                continue; // fully in shadow.
+            if let Some(pbr) = &hit.material.pbr {
+                // point lights are pi times brighter than their color, so a white surface
+                // facing one comes out the light's color, like it does with phong.
+                let reflected = pbr.evaluate(&hit.normal, &v, &ld).multiply(&lc);
+                color = color.add(PI * hit.material.opacity, &reflected);
+                continue;
             }
-            let lc = &light_filter * &lc;
 
             // reflection of direction to light
             let lr = ld.clone().add(-2., &ld.clone().off_axis(&hit.normal));
@@ -204,7 +246,35 @@ impl Scene {
                 .add(specular_strength, &hit.material.specular)
         }
 
-        if hit.material.reflectivity > 0. && refl_count > 0 {
+        // ambient light stands in for light bouncing around the scene, which creases and
+        // corners see less of. it's looked at after the lights, so they get the same random
+        // numbers either way.
+        if !ambient.is_black() {
+            let visibility = match &self.occlusion {
+                Some(occlusion) => occlusion.visibility(self.sdf.as_ref(), &hit.point, &hit.normal, rng),
+                None => 1.,
+            };
+            color = color.add(hit.material.opacity * visibility, &ambient);
+        }
+
+        // pbr materials reflect as much as their fresnel term says, rather than by reflectivity.
+        let reflectance = match &hit.material.pbr {
+            Some(pbr) => pbr.mirror_reflectance(&hit.normal, &v),
+            None => Color::white().scale(hit.material.reflectivity),
+        };
+        // the transparent part of the surface reflects more the more glancing the view is, and
+        // lets the rest through.
+        let transmission = 1. - hit.material.opacity;
+        let entry_reflectance = if transmission > 0. {
+            fresnel(&v * &hit.normal, RefractionConstants::VACUUM, hit.material.index_of_refraction)
+        } else {
+            0.
+        };
+        let reflectance = reflectance
+            .scale(hit.material.opacity)
+            .add(transmission * entry_reflectance, &Color::white());
+
+        if !reflectance.is_black() && refl_count > 0 {
             let refl_ray = Ray::new(
                 adjusted_hit,
                 v.clone().add(-2., &v.clone().off_axis(&hit.normal)),
@@ -213,67 +283,194 @@ impl Scene {
                 panic!("cannot cast NaN reflection ray: {:#?} v={}, normal={}", refl_ray,
                        v, hit.normal);
             }
-            if let Some(refl_color) = self.raycast(refl_ray, refl_count - 1) {
-                color = color.add(hit.material.reflectivity * hit.material.opacity, &refl_color);
+            if let Some(refl_color) = self.raycast(refl_ray, refl_count - 1, rng) {
+                color = color.add(1., &refl_color.multiply(&reflectance));
             }
         }
 
-        if hit.material.opacity < 1. && refl_count > 0 {
+        if transmission > 0. && entry_reflectance < 1. && refl_count > 0 {
             if self.debugging {
                 log(&format!("firing refraction ray for material with opacity {}",
                              hit.material.opacity));
             }
-            let refr_ray = Ray::new(
-                hit.point.clone().add(-self.sdf.epsilon() * 2., &hit.normal),
-                refract(
-                    &hit.ray.direction,
-                    &hit.normal,
-                    RefractionConstants::VACUUM,
-                    hit.material.index_of_refraction,
-                ),
+            let refracted = refract(
+                &hit.ray.direction,
+                &hit.normal,
+                RefractionConstants::VACUUM,
+                hit.material.index_of_refraction,
             );
-            if self.debugging {
-                log(&format!("refraction ray: {}", refr_ray));
-            }
-            let inverse_sdf = NegatedRefSDF::new(&self.sdf);
-            if let Some(farside_hit) = inverse_sdf.raymarch(&refr_ray, self.far_plane) {
-                // hit the far side of the interior of this shape.
-                let refr_ray = Ray::new(
-                    farside_hit.point.clone().add(-self.sdf.epsilon() * 2., &farside_hit.normal),
-                    refract(
-                        &farside_hit.ray.direction,
-                        &farside_hit.normal,
-                        hit.material.index_of_refraction,
-                        RefractionConstants::VACUUM,
-                    ),
-                );
+            if let Some(refracted) = refracted {
+                let refr_ray = Ray::new(hit.point.clone().add(-self.sdf.epsilon() * 2., &hit.normal), refracted);
                 if self.debugging {
-                    log(&format!("refraction interior hit: {:#?}", farside_hit));
-                    log(&format!("hit interior of shape with refraction ray, firing again: {}", refr_ray));
+                    log(&format!("refraction ray: {}", refr_ray));
+                }
+                let refr_color = self.interior_color(refr_ray, &hit.material, refl_count, rng);
+                if self.debugging {
+                    log(&format!("transparency color: {}", refr_color));
                 }
-                // TODO: apply coloration based on how far we went through the material?
+                color = color.add(transmission * (1. - entry_reflectance), &refr_color);
+            }
+        }
+
+        color
+    }
+
+    /// The light coming out of a transparent shape along a ray travelling inside it. Each time
+    /// the ray reaches the surface, some of the light gets out (as much as the fresnel equations
+    /// say) and the rest reflects back in, so we keep following it until there's too little left
+    /// or we run out of bounces.
+    fn interior_color(&self, ray: Ray, material: &Material, refl_count: usize, rng: &mut Rng) -> Color {
+        let inverse_sdf = NegatedRefSDF::new(&self.sdf);
+        let epsilon = self.sdf.epsilon();
+        let ior = material.index_of_refraction;
+        let mut color = Color::black();
+        let mut ray = ray;
+        let mut weight = Color::white();
+        let strongest = |c: &Color| {
+            let (r, g, b): (f64, f64, f64) = c.into();
+            r.max(g).max(b)
+        };
+        // every bounce inside uses up one of the reflections, like bounces outside do.
+        for bounce in 0..refl_count {
+            let farside_hit = match inverse_sdf.raymarch(&ray, self.far_plane) {
+                Some(hit) => hit,
+                None => break,
+            };
+            if self.debugging {
+                log(&format!("refraction interior hit: {:#?}", farside_hit));
+            }
+            // the material soaks up some of the light on the way across.
+            weight = weight.multiply(&material.transmittance(farside_hit.point.dist(&ray.origin)));
+            // the far side's normal points back into the shape.
+            let direction = farside_hit.ray.direction.clone().normalize();
+            let reflectance = fresnel(-(&direction * &farside_hit.normal), ior, RefractionConstants::VACUUM);
+            let exiting = refract(&direction, &farside_hit.normal, ior, RefractionConstants::VACUUM)
+                // too little gets out to be worth following.
+                .filter(|_| strongest(&weight) * (1. - reflectance) >= MIN_INTERIOR_WEIGHT);
+            if let Some(exit) = exiting {
+                let refr_ray = Ray::new(farside_hit.point.clone().add(-epsilon * 2., &farside_hit.normal), exit);
                 if refr_ray.origin.is_nan() || refr_ray.direction.is_nan() {
                     panic!("cannot cast NaN refraction ray: {:#?} farside hit: {:#?}", refr_ray,
                            farside_hit);
                 }
-                if let Some(refr_color) = self.raycast(refr_ray, refl_count - 1) {
-                    if self.debugging {
-                        log(&format!("transparency color: {}", refr_color));
-                    }
+                if self.debugging {
+                    log(&format!("hit interior of shape with refraction ray, firing again: {}", refr_ray));
+                }
+                if let Some(refr_color) = self.raycast(refr_ray, refl_count - 1 - bounce, rng) {
                     // hit from ray shooting out the other side of this shape
-                    color = color.add(1.0 - hit.material.opacity, &refr_color);
+                    color = color.add(1. - reflectance, &refr_color.multiply(&weight));
                 }
             }
+            weight = weight.scale(reflectance);
+            if strongest(&weight) < MIN_INTERIOR_WEIGHT {
+                break;
+            }
+            ray = Ray::new(
+                farside_hit.point.clone().add(epsilon * 2., &farside_hit.normal),
+                direction.clone().add(-2., &direction.clone().on_axis(&farside_hit.normal)),
+            );
         }
-
         color
     }
 
+    /// The direction from the point towards a random spot on the light, and how much of the
+    /// light's color gets there from that spot. Averaging many of these gives soft shadows.
+    pub(crate) fn light_sample(&self, light: &Light, point: &Vec3, max_crossings: usize, rng: &mut Rng) -> (Vec3, Color) {
+        let color = light.color(point);
+        if color.is_black() {
+            // no need to look for shadows that wouldn't show.
+            return (light.shadow_ray(point).direction.normalize(), color);
+        }
+        if let Some(k) = self.penumbra {
+            let shadow_ray = light.shadow_ray(point);
+            let to_light = shadow_ray.direction.norm();
+            let target = point.clone().add(1., &shadow_ray.direction);
+            let filter = match self.sdf.raymarch(&shadow_ray, to_light) {
+                // the estimate only knows about distances, so it can't see through things, but
+                // looking through them gets their color and absorption.
+                Some(hit) if hit.material.opacity < 1. => self.shadow_filter(&target, point, max_crossings),
+                Some(_) => Color::black(),
+                None => Color::white().scale(self.sdf.soft_shadow(&shadow_ray, to_light, k)),
+            };
+            let color = color.scale(light.facing(&target, point)).multiply(&filter);
+            return (shadow_ray.direction.normalize(), color);
+        }
+        let target = light.sample_point(point, rng);
+        let filter = self.shadow_filter(&target, point, max_crossings);
+        let color = color.scale(light.facing(&target, point)).multiply(&filter);
+        ((&target - point).normalize(), color)
+    }
+
+    /// The light seen looking off into the distance, past everything in the scene.
+    pub(crate) fn background(&self, direction: &Vec3) -> Color {
+        self.lights.iter()
+            .filter_map(|light| light.background(direction))
+            .fold(Color::black(), |total, color| total.add(1., &color))
+    }
+
+    /// How much light gets from the target to the point. Anything opaque in the way blocks it,
+    /// and each transparent thing lets through as much as isn't opaque, less what its inside
+    /// absorbs over the distance the light crosses. Things that don't absorb anything tint the
+    /// light by their diffuse color instead, as much as they're opaque. This doesn't take
+    /// refraction into account.
+    pub(crate) fn shadow_filter(&self, target: &Vec3, point: &Vec3, max_crossings: usize) -> Color {
+        let epsilon = self.sdf.epsilon();
+        let inverse_sdf = NegatedRefSDF::new(&self.sdf);
+        let mut filter = Color::white();
+        let mut origin = point.clone();
+        for _ in 0..max_crossings {
+            let shadow_ray = Ray::new(origin.clone(), target - &origin);
+            let shadow_dir = shadow_ray.direction.clone().normalize();
+            let to_light = shadow_ray.direction.norm();
+            let hit = match self.sdf.raymarch(&shadow_ray, to_light) {
+                Some(hit) => hit,
+                None => break,
+            };
+            if &hit.normal * &shadow_dir >= 0. {
+                // we're leaving something, not entering it.
+                origin = hit.point.clone().add(epsilon * 2., &shadow_dir);
+                continue;
+            }
+            if hit.material.opacity >= 1. {
+                return Color::black();
+            }
+            if hit.material.absorption.is_black() {
+                filter = filter.multiply(&Color::white().lerp(hit.material.opacity, &hit.material.diffuse));
+            } else {
+                filter = filter.scale(1. - hit.material.opacity);
+            }
+            let inside = Ray::new(hit.point.clone().add(epsilon * 2., &shadow_dir), shadow_dir.clone());
+            let remaining = to_light - hit.point.dist(&origin);
+            match inverse_sdf.raymarch(&inside, remaining) {
+                Some(exit) => {
+                    filter = filter.multiply(&hit.material.transmittance(exit.point.dist(&hit.point)));
+                    origin = exit.point.clone().add(epsilon * 2., &shadow_dir);
+                }
+                None => {
+                    // the light is inside it.
+                    return filter.multiply(&hit.material.transmittance(remaining));
+                }
+            }
+        }
+        filter
+    }
+
     pub fn parse<'a, L>(lines: L) -> Self where L: Iterator<Item=&'a str> {
+        Self::parse_with_files(lines, &HashMap::new())
+    }
+
+    /// Like `parse`, but files the scene refers to are looked up in `files` (by the path the
+    /// scene uses) before trying to read them.
+    pub fn parse_with_files<'a, L>(lines: L, files: &HashMap<String, Vec<u8>>) -> Self
+        where L: Iterator<Item=&'a str> {
         let mut objects: Vec<Box<dyn SDF>> = vec![];
         let mut lights: Vec<Light> = vec![];
         let mut view: ViewTransform = ViewTransform::Ortho(OrthoView { frame: Frame::identity() });
         let mut far_plane = 1_000.;
+        let mut sampler = Sampler::new();
+        let mut integrator = Integrator::Whitted;
+        let mut penumbra = None;
+        let mut occlusion = None;
 
         let default_attenuation = 50.;
 
@@ -281,6 +478,29 @@ impl Scene {
 
         let mut material = Material::new();
         let mut poly_vertices: Vec<Vec3> = vec![];
+        // fractals blend from the current material to this color along their orbit trap.
+        let mut trap_color: Option<Color> = None;
+        // material bands for the next heightfield, over its own material.
+        let mut bands: Vec<(f64, f64, Material)> = vec![];
+        // curves blend from the current material to this color along their length.
+        let mut gradient_color: Option<Color> = None;
+        // text is wrapped to lines of this many characters, if it's set.
+        let mut text_columns: Option<usize> = None;
+        let trap_palette = |material: &Material, trap_color: &Option<Color>| {
+            let mut far = material.clone();
+            far.diffuse = trap_color.clone().unwrap_or_else(|| material.diffuse.clone());
+            TrapPalette::new(material.clone(), far)
+        };
+        let shade_curve = |curve: CurveTube, material: &Material, gradient_color: &Option<Color>| -> Box<dyn SDF> {
+            match gradient_color {
+                Some(color) => {
+                    let mut end = material.clone();
+                    end.diffuse = color.clone();
+                    Box::new(curve.gradient(material.clone(), end))
+                }
+                None => Box::new(curve.shaded(material.clone())),
+            }
+        };
 
         for line in lines {
             let line: &str = line.trim();
@@ -303,6 +523,104 @@ impl Scene {
                         fov_degrees: fov.parse().unwrap(),
                     })
                 }
+                ("samples", [count, pattern]) => {
+                    sampler.samples = count.parse().unwrap();
+                    match SamplePattern::parse(pattern) {
+                        Ok(pattern) => sampler.pattern = pattern,
+                        Err(e) => log(&e),
+                    }
+                }
+                ("adaptive", [threshold]) => {
+                    sampler.adaptive = Some(threshold.parse().unwrap());
+                }
+                ("integrator", ["whitted"]) => integrator = Integrator::Whitted,
+                ("integrator", ["path", depth]) => {
+                    integrator = Integrator::PathTracer(PathTracer::new(depth.parse().unwrap()));
+                }
+                ("pbr", []) => material.pbr = Some(Pbr::from_phong(&material)),
+                ("pbr", [r, g, b, metallic, roughness]) => {
+                    material.pbr = Some(Pbr::new(
+                        Color::new(r.parse().unwrap(), g.parse().unwrap(), b.parse().unwrap()),
+                        metallic.parse().unwrap(),
+                        roughness.parse().unwrap(),
+                    ));
+                }
+                ("transparent", [opacity, ior]) => {
+                    material.opacity = opacity.parse().unwrap();
+                    material.index_of_refraction = ior.parse().unwrap();
+                }
+                ("absorb", [r, g, b, density]) => {
+                    material.absorption = Color::new(r.parse().unwrap(), g.parse().unwrap(), b.parse().unwrap());
+                    material.density = density.parse().unwrap();
+                }
+                ("filter", [filter]) => {
+                    match Filter::parse(filter) {
+                        Ok(filter) => sampler.filter = filter,
+                        Err(e) => log(&e),
+                    }
+                }
+                ("spherelight", [radius, x, y, z, r, g, b]) => {
+                    lights.push(Light::new(
+                        Vec3::new(x.parse().unwrap(), y.parse().unwrap(), z.parse().unwrap()),
+                        Color::new(r.parse().unwrap(), g.parse().unwrap(), b.parse().unwrap()),
+                        default_attenuation,
+                    ).sphere(radius.parse().unwrap()));
+                }
+                ("rectlight", [x, y, z, ux, uy, uz, vx, vy, vz, r, g, b]) => {
+                    lights.push(Light::new(
+                        Vec3::new(x.parse().unwrap(), y.parse().unwrap(), z.parse().unwrap()),
+                        Color::new(r.parse().unwrap(), g.parse().unwrap(), b.parse().unwrap()),
+                        default_attenuation,
+                    ).rect(
+                        Vec3::new(ux.parse().unwrap(), uy.parse().unwrap(), uz.parse().unwrap()),
+                        Vec3::new(vx.parse().unwrap(), vy.parse().unwrap(), vz.parse().unwrap()),
+                    ));
+                }
+                ("disklight", [radius, x, y, z, nx, ny, nz, r, g, b]) => {
+                    lights.push(Light::new(
+                        Vec3::new(x.parse().unwrap(), y.parse().unwrap(), z.parse().unwrap()),
+                        Color::new(r.parse().unwrap(), g.parse().unwrap(), b.parse().unwrap()),
+                        default_attenuation,
+                    ).disk(
+                        Vec3::new(nx.parse().unwrap(), ny.parse().unwrap(), nz.parse().unwrap()),
+                        radius.parse().unwrap(),
+                    ));
+                }
+                ("penumbra", [k]) => penumbra = Some(k.parse().unwrap()),
+                ("occlusion", ["off"]) => occlusion = None,
+                ("occlusion", [steps, distance, strength, rest @ ..]) if rest.len() <= 1 => {
+                    let mut ao = AmbientOcclusion::new();
+                    ao.steps = steps.parse().unwrap();
+                    ao.distance = distance.parse().unwrap();
+                    ao.strength = strength.parse().unwrap();
+                    if let [samples] = rest {
+                        ao.samples = samples.parse().unwrap();
+                    }
+                    occlusion = Some(ao);
+                }
+                ("sunlight", [dx, dy, dz, r, g, b]) => {
+                    lights.push(Light::sun(
+                        Vec3::new(dx.parse().unwrap(), dy.parse().unwrap(), dz.parse().unwrap()),
+                        Color::new(r.parse().unwrap(), g.parse().unwrap(), b.parse().unwrap()),
+                    ));
+                }
+                ("spotlight", [x, y, z, dx, dy, dz, angle, falloff, r, g, b]) => {
+                    lights.push(Light::new(
+                        Vec3::new(x.parse().unwrap(), y.parse().unwrap(), z.parse().unwrap()),
+                        Color::new(r.parse().unwrap(), g.parse().unwrap(), b.parse().unwrap()),
+                        default_attenuation,
+                    ).spot(
+                        Vec3::new(dx.parse().unwrap(), dy.parse().unwrap(), dz.parse().unwrap()),
+                        angle.parse().unwrap(),
+                        falloff.parse().unwrap(),
+                    ));
+                }
+                ("skylight", [r, g, b, gr, gg, gb]) => {
+                    lights.push(Light::sky(
+                        Color::new(r.parse().unwrap(), g.parse().unwrap(), b.parse().unwrap()),
+                        Color::new(gr.parse().unwrap(), gg.parse().unwrap(), gb.parse().unwrap()),
+                    ));
+                }
                 ("light", [x, y, z, r, g, b]) => {
                     let light = Light::new(
                         Vec3::new(x.parse().unwrap(), y.parse().unwrap(), z.parse().unwrap()),
@@ -335,6 +653,368 @@ impl Scene {
                             .shaded(material.clone())
                     ));
                 }
+                ("box", [sx, sy, sz, x, y, z]) => {
+                    objects.push(Box::new(
+                        Cuboid::new(Vec3::new(sx.parse().unwrap(), sy.parse().unwrap(), sz.parse().unwrap()))
+                            .translate(Vec3::new(x.parse().unwrap(), y.parse().unwrap(), z.parse().unwrap()))
+                            .shaded(material.clone())
+                    ));
+                }
+                ("roundbox", [sx, sy, sz, radius, x, y, z]) => {
+                    objects.push(Box::new(
+                        RoundedCuboid::new(
+                            Vec3::new(sx.parse().unwrap(), sy.parse().unwrap(), sz.parse().unwrap()),
+                            radius.parse().unwrap(),
+                        )
+                            .translate(Vec3::new(x.parse().unwrap(), y.parse().unwrap(), z.parse().unwrap()))
+                            .shaded(material.clone())
+                    ));
+                }
+                ("torus", [major, minor, x, y, z]) => {
+                    objects.push(Box::new(
+                        Torus::new(major.parse().unwrap(), minor.parse().unwrap())
+                            .translate(Vec3::new(x.parse().unwrap(), y.parse().unwrap(), z.parse().unwrap()))
+                            .shaded(material.clone())
+                    ));
+                }
+                ("capsule", [ax, ay, az, bx, by, bz, radius]) => {
+                    objects.push(Box::new(
+                        Capsule::new(
+                            Vec3::new(ax.parse().unwrap(), ay.parse().unwrap(), az.parse().unwrap()),
+                            Vec3::new(bx.parse().unwrap(), by.parse().unwrap(), bz.parse().unwrap()),
+                            radius.parse().unwrap(),
+                        ).shaded(material.clone())
+                    ));
+                }
+                ("cylinder", [radius, height, x, y, z]) => {
+                    objects.push(Box::new(
+                        Cylinder::new(radius.parse().unwrap(), height.parse().unwrap())
+                            .translate(Vec3::new(x.parse().unwrap(), y.parse().unwrap(), z.parse().unwrap()))
+                            .shaded(material.clone())
+                    ));
+                }
+                ("cone", [height, bottom_radius, top_radius, x, y, z]) => {
+                    objects.push(Box::new(
+                        Cone::new(height.parse().unwrap(), bottom_radius.parse().unwrap(), top_radius.parse().unwrap())
+                            .translate(Vec3::new(x.parse().unwrap(), y.parse().unwrap(), z.parse().unwrap()))
+                            .shaded(material.clone())
+                    ));
+                }
+                ("ellipsoid", [rx, ry, rz, x, y, z]) => {
+                    objects.push(Box::new(
+                        Ellipsoid::new(Vec3::new(rx.parse().unwrap(), ry.parse().unwrap(), rz.parse().unwrap()))
+                            .translate(Vec3::new(x.parse().unwrap(), y.parse().unwrap(), z.parse().unwrap()))
+                            .shaded(material.clone())
+                    ));
+                }
+                ("hexprism", [radius, height, x, y, z]) => {
+                    objects.push(Box::new(
+                        HexPrism::new(radius.parse().unwrap(), height.parse().unwrap())
+                            .translate(Vec3::new(x.parse().unwrap(), y.parse().unwrap(), z.parse().unwrap()))
+                            .shaded(material.clone())
+                    ));
+                }
+                ("octahedron", [size, x, y, z]) => {
+                    objects.push(Box::new(
+                        Octahedron::new(size.parse().unwrap())
+                            .translate(Vec3::new(x.parse().unwrap(), y.parse().unwrap(), z.parse().unwrap()))
+                            .shaded(material.clone())
+                    ));
+                }
+                ("mesh", [path, scale, x, y, z]) => {
+                    let mesh = read_file(files, path)
+                        .and_then(|bytes| String::from_utf8(bytes).map_err(|e| e.to_string()))
+                        .and_then(|source| TriangleMesh::from_obj(&source));
+                    match mesh {
+                        Ok(mesh) => objects.push(Box::new(
+                            mesh.scale(scale.parse().unwrap())
+                                .translate(Vec3::new(x.parse().unwrap(), y.parse().unwrap(), z.parse().unwrap()))
+                                .shaded(material.clone())
+                        )),
+                        Err(e) => log(&format!("couldn't load mesh: {}", e)),
+                    }
+                }
+                ("voxels", [path, interpolation, x, y, z]) => {
+                    let interpolation = match Interpolation::parse(interpolation) {
+                        Ok(interpolation) => interpolation,
+                        Err(e) => {
+                            log(&e);
+                            continue;
+                        }
+                    };
+                    match read_file(files, path).and_then(|bytes| VoxelGrid::from_bytes(&bytes, interpolation)) {
+                        Ok(grid) => {
+                            let has_materials = grid.has_materials();
+                            let grid = grid.translate(Vec3::new(x.parse().unwrap(), y.parse().unwrap(), z.parse().unwrap()));
+                            // baked grids keep the materials they were baked with.
+                            if has_materials {
+                                objects.push(Box::new(grid));
+                            } else {
+                                objects.push(Box::new(grid.shaded(material.clone())));
+                            }
+                        }
+                        Err(e) => log(&format!("couldn't load voxel grid: {}", e)),
+                    }
+                }
+                ("heightfield", [path, sx, sy, sz]) => {
+                    let size = Vec3::new(sx.parse().unwrap(), sy.parse().unwrap(), sz.parse().unwrap());
+                    match read_file(files, path).and_then(|bytes| Heightfield::from_pgm(&bytes, size)) {
+                        Ok(terrain) => {
+                            let terrain = bands.drain(..).fold(
+                                terrain.band(f64::NEG_INFINITY, f64::INFINITY, material.clone()),
+                                |terrain, (altitude, max_slope, band)| terrain.band(altitude, max_slope, band),
+                            );
+                            objects.push(Box::new(terrain));
+                        }
+                        Err(e) => log(&format!("couldn't load heightfield: {}", e)),
+                    }
+                }
+                ("band", [altitude, max_slope]) => {
+                    bands.push((altitude.parse().unwrap(), max_slope.parse().unwrap(), material.clone()));
+                }
+                ("trapcolor", [r, g, b]) => {
+                    trap_color = Some(Color::new(r.parse().unwrap(), g.parse().unwrap(), b.parse().unwrap()));
+                }
+                ("mandelbulb", [power, iterations, size, x, y, z]) => {
+                    objects.push(Box::new(
+                        Mandelbulb::new(power.parse().unwrap(), iterations.parse().unwrap())
+                            .palette(trap_palette(&material, &trap_color))
+                            .scale(size.parse().unwrap())
+                            .translate(Vec3::new(x.parse().unwrap(), y.parse().unwrap(), z.parse().unwrap()))
+                    ));
+                }
+                ("menger", [iterations, size, x, y, z]) => {
+                    objects.push(Box::new(
+                        MengerSponge::new(iterations.parse().unwrap())
+                            .palette(trap_palette(&material, &trap_color))
+                            .scale(size.parse().unwrap())
+                            .translate(Vec3::new(x.parse().unwrap(), y.parse().unwrap(), z.parse().unwrap()))
+                    ));
+                }
+                ("sierpinski", [iterations, size, x, y, z]) => {
+                    objects.push(Box::new(
+                        SierpinskiTetrahedron::new(iterations.parse().unwrap())
+                            .palette(trap_palette(&material, &trap_color))
+                            .scale(size.parse().unwrap())
+                            .translate(Vec3::new(x.parse().unwrap(), y.parse().unwrap(), z.parse().unwrap()))
+                    ));
+                }
+                ("julia", [cw, cx, cy, cz, iterations, size, x, y, z]) => {
+                    let c = [cw.parse().unwrap(), cx.parse().unwrap(), cy.parse().unwrap(), cz.parse().unwrap()];
+                    objects.push(Box::new(
+                        JuliaSet::new(c, iterations.parse().unwrap())
+                            .palette(trap_palette(&material, &trap_color))
+                            .scale(size.parse().unwrap())
+                            .translate(Vec3::new(x.parse().unwrap(), y.parse().unwrap(), z.parse().unwrap()))
+                    ));
+                }
+                ("text", [path, size, depth, x, y, z, words @ ..]) if !words.is_empty() => {
+                    match read_file(files, path).and_then(Font::new) {
+                        Ok(font) => objects.push(Box::new(
+                            match text_columns {
+                                Some(columns) => font.wrapped_text(&words.join(" "), size.parse().unwrap(), columns),
+                                None => font.text(&words.join(" "), size.parse().unwrap()),
+                            }
+                                .extrude(depth.parse().unwrap())
+                                .translate(Vec3::new(x.parse().unwrap(), y.parse().unwrap(), z.parse().unwrap()))
+                                .shaded(material.clone())
+                        )),
+                        Err(e) => log(&format!("couldn't load font: {}", e)),
+                    }
+                }
+                ("wrap", [columns]) => {
+                    text_columns = match columns.parse().unwrap() {
+                        0 => None,
+                        columns => Some(columns),
+                    };
+                }
+                ("gradient", [r, g, b]) => {
+                    gradient_color = Some(Color::new(r.parse().unwrap(), g.parse().unwrap(), b.parse().unwrap()));
+                }
+                ("gradient", ["off"]) => gradient_color = None,
+                ("bezier", [ax, ay, az, bx, by, bz, cx, cy, cz, r0, r1]) => {
+                    let curve = CurveTube::quadratic(
+                        Vec3::new(ax.parse().unwrap(), ay.parse().unwrap(), az.parse().unwrap()),
+                        Vec3::new(bx.parse().unwrap(), by.parse().unwrap(), bz.parse().unwrap()),
+                        Vec3::new(cx.parse().unwrap(), cy.parse().unwrap(), cz.parse().unwrap()),
+                        (r0.parse().unwrap(), r1.parse().unwrap()),
+                    );
+                    objects.push(shade_curve(curve, &material, &gradient_color));
+                }
+                ("bezier", [ax, ay, az, bx, by, bz, cx, cy, cz, dx, dy, dz, r0, r1]) => {
+                    let curve = CurveTube::cubic(
+                        Vec3::new(ax.parse().unwrap(), ay.parse().unwrap(), az.parse().unwrap()),
+                        Vec3::new(bx.parse().unwrap(), by.parse().unwrap(), bz.parse().unwrap()),
+                        Vec3::new(cx.parse().unwrap(), cy.parse().unwrap(), cz.parse().unwrap()),
+                        Vec3::new(dx.parse().unwrap(), dy.parse().unwrap(), dz.parse().unwrap()),
+                        (r0.parse().unwrap(), r1.parse().unwrap()),
+                    );
+                    objects.push(shade_curve(curve, &material, &gradient_color));
+                }
+                ("spline", [radius, coords @ ..]) if coords.len() % 3 == 0 => {
+                    let points: Vec<Vec3> = coords.chunks(3)
+                        .map(|c| Vec3::new(c[0].parse().unwrap(), c[1].parse().unwrap(), c[2].parse().unwrap()))
+                        .collect();
+                    let radii = vec![radius.parse().unwrap(); points.len()];
+                    match CurveTube::catmull_rom(points, radii) {
+                        Ok(spline) => objects.push(shade_curve(spline, &material, &gradient_color)),
+                        Err(e) => log(&format!("couldn't make spline: {}", e)),
+                    }
+                }
+                ("revolve", ["circle", radius, offset, x, y, z]) => {
+                    objects.push(Box::new(
+                        Circle::new(radius.parse().unwrap())
+                            .revolve(offset.parse().unwrap())
+                            .translate(Vec3::new(x.parse().unwrap(), y.parse().unwrap(), z.parse().unwrap()))
+                            .shaded(material.clone())
+                    ));
+                }
+                ("revolve", ["rect", width, height, offset, x, y, z]) => {
+                    objects.push(Box::new(
+                        Rect::new(width.parse().unwrap(), height.parse().unwrap())
+                            .revolve(offset.parse().unwrap())
+                            .translate(Vec3::new(x.parse().unwrap(), y.parse().unwrap(), z.parse().unwrap()))
+                            .shaded(material.clone())
+                    ));
+                }
+                // the rest change the last object (or, for the smooth ones, the last two).
+                ("repeat", [sx, sy, sz]) => {
+                    let spacing = Vec3::new(sx.parse().unwrap(), sy.parse().unwrap(), sz.parse().unwrap());
+                    modify_last(&mut objects, command, |sdf| Box::new(sdf.repeat(spacing)));
+                }
+                ("repeat", [sx, sy, sz, nx, ny, nz]) => {
+                    let spacing = Vec3::new(sx.parse().unwrap(), sy.parse().unwrap(), sz.parse().unwrap());
+                    let count = (nx.parse().unwrap(), ny.parse().unwrap(), nz.parse().unwrap());
+                    modify_last(&mut objects, command, |sdf| Box::new(sdf.repeat_limited(spacing, count)));
+                }
+                ("polarrepeat", [ax, ay, az, count]) => {
+                    let axis = Vec3::new(ax.parse().unwrap(), ay.parse().unwrap(), az.parse().unwrap());
+                    let count = count.parse().unwrap();
+                    modify_last(&mut objects, command, |sdf| Box::new(sdf.repeat_polar(axis, count)));
+                }
+                ("twist", [rate, radius]) => {
+                    let (rate, radius) = (rate.parse().unwrap(), radius.parse().unwrap());
+                    modify_last(&mut objects, command, |sdf| Box::new(sdf.twist(rate, radius)));
+                }
+                ("bend", [rate, radius]) => {
+                    let (rate, radius) = (rate.parse().unwrap(), radius.parse().unwrap());
+                    modify_last(&mut objects, command, |sdf| Box::new(sdf.bend(rate, radius)));
+                }
+                ("taper", [rate, height, radius]) => {
+                    let (rate, height, radius): (f64, f64, f64) =
+                        (rate.parse().unwrap(), height.parse().unwrap(), radius.parse().unwrap());
+                    if 1. - rate.abs() * height <= 0. {
+                        log(&format!("taper rate {} would collapse the shape within height {}", rate, height));
+                        continue;
+                    }
+                    modify_last(&mut objects, command, |sdf| Box::new(sdf.taper(rate, height, radius)));
+                }
+                ("displace", [amplitude, noise @ ..]) => {
+                    let amplitude = amplitude.parse().unwrap();
+                    match parse_noise(noise) {
+                        Ok(noise) => modify_last(&mut objects, command, |sdf| {
+                            Box::new(sdf.displace(noise.displacement(amplitude)))
+                        }),
+                        Err(e) => log(&e),
+                    }
+                }
+                ("textured", [r, g, b, noise @ ..]) => {
+                    let mut high = material.clone();
+                    high.diffuse = Color::new(r.parse().unwrap(), g.parse().unwrap(), b.parse().unwrap());
+                    let low = material.clone();
+                    match parse_noise(noise) {
+                        Ok(noise) => modify_last(&mut objects, command, |sdf| Box::new(sdf.textured(noise, low, high))),
+                        Err(e) => log(&e),
+                    }
+                }
+                ("round", [radius]) => {
+                    let radius = radius.parse().unwrap();
+                    modify_last(&mut objects, command, |sdf| Box::new(sdf.round(radius)));
+                }
+                ("shell", [thickness]) => {
+                    let thickness: f64 = thickness.parse().unwrap();
+                    if thickness <= 0. {
+                        log(&format!("shell thickness has to be positive, got {}", thickness));
+                        continue;
+                    }
+                    modify_last(&mut objects, command, |sdf| Box::new(sdf.shell(thickness)));
+                }
+                ("elongate", [hx, hy, hz]) => {
+                    let half_length = Vec3::new(hx.parse().unwrap(), hy.parse().unwrap(), hz.parse().unwrap());
+                    modify_last(&mut objects, command, |sdf| Box::new(sdf.elongate(half_length)));
+                }
+                ("mirror", [nx, ny, nz, offset]) => {
+                    let normal = Vec3::new(nx.parse().unwrap(), ny.parse().unwrap(), nz.parse().unwrap());
+                    let offset = offset.parse().unwrap();
+                    modify_last(&mut objects, command, |sdf| Box::new(sdf.mirror(normal, offset)));
+                }
+                ("symmetric", [axes]) => {
+                    let axes = (axes.contains('x'), axes.contains('y'), axes.contains('z'));
+                    modify_last(&mut objects, command, |sdf| Box::new(sdf.symmetric(axes)));
+                }
+                ("smoothunion" | "smoothintersect" | "smoothdifference", kernel) => {
+                    let kernel = match SmoothUnionType::parse(kernel) {
+                        Ok(kernel) => Some(kernel),
+                        Err(e) => {
+                            log(&e);
+                            continue;
+                        }
+                    };
+                    if objects.len() < 2 {
+                        log(&format!("{} needs two objects before it", command));
+                        continue;
+                    }
+                    let b = objects.pop().unwrap();
+                    let a = objects.pop().unwrap();
+                    objects.push(match command {
+                        "smoothunion" => Box::new(a.smooth_union(b, kernel)),
+                        "smoothintersect" => Box::new(a.smooth_intersection(b, kernel)),
+                        _ => Box::new(a.smooth_difference(b, kernel)),
+                    });
+                }
+                ("bake", [resolution, interpolation, path @ ..]) if path.len() <= 1 => {
+                    let resolution = resolution.parse().unwrap();
+                    let interpolation = match Interpolation::parse(interpolation) {
+                        Ok(interpolation) => interpolation,
+                        Err(e) => {
+                            log(&e);
+                            continue;
+                        }
+                    };
+                    let sdf = match objects.pop() {
+                        Some(sdf) => sdf,
+                        None => {
+                            log("bake needs an object before it");
+                            continue;
+                        }
+                    };
+                    let bounds = match sdf.bounds() {
+                        // leave some room around the surface, so it isn't right on the edge.
+                        Some(bounds) => {
+                            let margin = bounds.size().scale(0.1);
+                            bounds.expand(&margin)
+                        }
+                        None => {
+                            log("can't bake an object without bounds");
+                            objects.push(sdf);
+                            continue;
+                        }
+                    };
+                    match VoxelGrid::bake(sdf.as_ref(), bounds, (resolution, resolution, resolution), interpolation) {
+                        Ok(grid) => {
+                            if let Some(path) = path.first() {
+                                if let Err(e) = save_grid(&grid, path) {
+                                    log(&format!("couldn't save voxel grid: {}", e));
+                                }
+                            }
+                            objects.push(Box::new(grid));
+                        }
+                        Err(e) => {
+                            log(&format!("couldn't bake voxel grid: {}", e));
+                            objects.push(sdf);
+                        }
+                    }
+                }
                 ("background", [r, g, b]) => {
                     objects.push(Box::new(
                         Sphere::new(100.)
@@ -355,11 +1035,12 @@ impl Scene {
                 ("begin", []) => {
                     poly_vertices.clear();
                 }
-                ("end", []) => {
-                    objects.push(Box::new(
-                        PolyFace::new(poly_vertices.clone())
-                            .shaded(material.clone())
-                    ));
+                ("end", thickness) if thickness.len() <= 1 => {
+                    let thickness = thickness.first().map_or(0.1, |t| t.parse().unwrap());
+                    match PolyFace::new(poly_vertices.clone(), thickness) {
+                        Ok(face) => objects.push(Box::new(face.shaded(material.clone()))),
+                        Err(e) => log(&format!("couldn't make polygon: {}", e)),
+                    }
                 }
                 ("vertex", [x, y, z]) => {
                     poly_vertices.push(Vec3::new(
@@ -368,7 +1049,7 @@ impl Scene {
                         z.parse().unwrap(),
                     ));
                 }
-                ("write", [filepath]) => {
+                ("write", [_filepath]) => {
                     // ignored
                 }
                 (command, args) => {
@@ -392,28 +1073,40 @@ impl Scene {
             view,
             far_plane,
             debugging: false,
+            seed: 0,
+            sampler,
+            integrator,
+            penumbra,
+            occlusion,
         }
     }
 }
 
-fn refract(incoming: &Vec3, normal: &Vec3, n1: f64, n2: f64) -> Vec3 {
+/// The direction light travelling along `incoming` goes after crossing from a medium with index
+/// of refraction `n1` into one with `n2`, or nothing if it's reflected back by total internal
+/// reflection.
+pub(crate) fn refract(incoming: &Vec3, normal: &Vec3, n1: f64, n2: f64) -> Option<Vec3> {
     // n1 sin theta1 = n2 sin theta2
     // sin theta2 =  (n1 sin theta1) / n2
     // theta2 = asin ((n1 sin theta1) / n2)
 
     if (n1 - n2).abs() < 0.0001 {
-        return incoming.clone();
+        return Some(incoming.clone());
     }
 
     let incoming = incoming.clone().normalize();
     let cos_theta1 = -(&incoming * normal);
     if (cos_theta1.abs() - 1.).abs() < 0.0001 {
         // if the incoming ray is orthogonal to the surface, there isn't any refraction to do.
-        return incoming.clone();
+        return Some(incoming);
     }
 
     let theta1 = cos_theta1.clamp(-1., 1.).acos().abs();
-    let theta2 = ((n1 * theta1.sin()) / n2).clamp(-1., 1.).asin();
+    let sin_theta2 = (n1 * theta1.sin()) / n2;
+    if sin_theta2 > 1. {
+        return None;
+    }
+    let theta2 = sin_theta2.asin();
 
     let axis_of_rotation = (&incoming ^ normal).normalize();
 
@@ -428,33 +1121,69 @@ fn refract(incoming: &Vec3, normal: &Vec3, n1: f64, n2: f64) -> Vec3 {
         );
     }
 
-    result
+    Some(result)
+}
+
+/// The fraction of unpolarized light reflected where a medium with index of refraction `n1`
+/// meets one with `n2`, by the exact Fresnel equations for dielectrics. `cos_incident` is the
+/// cosine of the angle between the incoming light and the normal.
+pub(crate) fn fresnel(cos_incident: f64, n1: f64, n2: f64) -> f64 {
+    let cos_i = cos_incident.abs().min(1.);
+    let sin_t = n1 / n2 * (1. - cos_i * cos_i).sqrt();
+    if sin_t >= 1. {
+        return 1.;
+    }
+    let cos_t = (1. - sin_t * sin_t).sqrt();
+    let rs = (n1 * cos_i - n2 * cos_t) / (n1 * cos_i + n2 * cos_t);
+    let rp = (n1 * cos_t - n2 * cos_i) / (n1 * cos_t + n2 * cos_i);
+    (rs * rs + rp * rp) / 2.
+}
+
+/// Replaces the last object with a changed version of it.
+fn modify_last<F>(objects: &mut Vec<Box<dyn SDF>>, command: &str, modify: F)
+    where F: FnOnce(Box<dyn SDF>) -> Box<dyn SDF> {
+    match objects.pop() {
+        Some(sdf) => objects.push(modify(sdf)),
+        None => log(&format!("{} needs an object before it", command)),
+    }
 }
 
-fn perturb(ray: &Ray, degrees: f64) -> Ray {
-    let random_spread = degrees * PI / 180.0;
-    let mut ray = ray.clone();
-
-    let r = ray.direction.clone().normalize();
-    let axis1 = Vec3::cross(&r, &Vec3::right());
-    let axis1 = if axis1.norm2() == 0. {
-        Vec3::cross(&r, &Vec3::up())
-    } else {
-        axis1
-    };
-    let axis1 = axis1.normalize();
-    let axis2 = Vec3::cross(&axis1, &r).normalize();
-
-    ray.direction = r
-        .rotate((random() * 2. - 1.) * random_spread, &axis1)
-        .rotate((random() * 2. - 1.) * random_spread, &axis2);
-    ray
+#[cfg(not(target_arch = "wasm32"))]
+fn save_grid(grid: &VoxelGrid, path: &str) -> Result<(), String> {
+    grid.save(path)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn save_grid(_grid: &VoxelGrid, path: &str) -> Result<(), String> {
+    Err(format!("browsers can't write {}", path))
+}
+
+/// The contents of a file the scene refers to, if it was handed over with the scene.
+fn read_file(files: &HashMap<String, Vec<u8>>, path: &str) -> Result<Vec<u8>, String> {
+    match files.get(path) {
+        Some(bytes) => Ok(bytes.clone()),
+        None => read_from_disk(path),
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_from_disk(path: &str) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|e| format!("couldn't read {}: {}", path, e))
+}
+
+/// Browsers can't read files, so they have to be fetched and handed over with the scene.
+#[cfg(target_arch = "wasm32")]
+fn read_from_disk(path: &str) -> Result<Vec<u8>, String> {
+    Err(format!("{} wasn't added to the viewport", path))
 }
 
 #[cfg(test)]
 mod tests {
     use crate::linear::Vec3;
-    use crate::scene::refract;
+    use crate::mat::Color;
+    use crate::light::Light;
+    use crate::rng::Rng;
+    use crate::scene::{fresnel, refract, Scene};
     use wasm_bindgen::__rt::core::f64::consts::PI;
 
     #[test]
@@ -488,7 +1217,7 @@ mod tests {
     }
 
     fn check_snell(incoming: &Vec3, normal: &Vec3, n1: f64, n2: f64) -> Vec3 {
-        let refracted = refract(incoming, normal, n1, n2);
+        let refracted = refract(incoming, normal, n1, n2).unwrap();
         let theta1 = (incoming * normal).acos();
         let theta2 = (&refracted * normal).acos();
         assert!(
@@ -526,4 +1255,242 @@ mod tests {
 
         refracted
     }
+
+    #[test]
+    fn total_internal_reflection() {
+        // glass to air past the critical angle (about 41.8°) can't get out.
+        let steep = Vec3::new(1., -1., 0.).normalize();
+        let normal = Vec3::up();
+        assert!(refract(&steep, &normal, 1.5, 1.).is_none());
+        assert!(refract(&Vec3::new(0.5, -1., 0.).normalize(), &normal, 1.5, 1.).is_some());
+        assert_eq!(fresnel(steep.y, 1.5, 1.), 1.);
+
+        // about 4% of light is reflected head-on by glass, all of it at grazing angles, and
+        // none where nothing changes.
+        assert!((fresnel(1., 1., 1.5) - 0.04).abs() < 1e-9);
+        assert!(fresnel(0., 1., 1.5) > 0.999);
+        assert!(fresnel(0.5, 1.2, 1.2) < 1e-12);
+    }
+
+    #[test]
+    fn shadows_through_absorbing_glass() {
+        let scene = Scene::parse("transparent 0 1.5\nabsorb 1 0 0.5 0.5\nsphere 1 0 0 0".lines());
+        let light = Vec3::new(0., 5., 0.);
+        // the light crosses 2 units of glass, through the middle of the sphere.
+        let filter = scene.shadow_filter(&light, &Vec3::new(0., -5., 0.), 10);
+        let (r, g, b): (f64, f64, f64) = (&filter).into();
+        assert!((r - (-1f64).exp()).abs() < 0.01, "{}", filter);
+        assert!((g - 1.).abs() < 1e-9);
+        assert!((b - (-0.5f64).exp()).abs() < 0.01, "{}", filter);
+        // off to the side, nothing's in the way.
+        assert_eq!(scene.shadow_filter(&light, &Vec3::new(3., -5., 0.), 10), Color::white());
+
+        // colored glass that doesn't absorb anything still tints its shadow.
+        let scene = Scene::parse("surface 1 0 0  0 0 0  0 0 0  1 0\ntransparent 0.5 1.5\nsphere 1 0 0 0".lines());
+        assert_eq!(scene.shadow_filter(&light, &Vec3::new(0., -5., 0.), 10), Color::new(1., 0.5, 0.5));
+    }
+
+    #[test]
+    fn penumbra_sees_through_glass() {
+        let scene = Scene::parse("penumbra 8\ntransparent 0 1.5\nabsorb 1 0 0.5 0.5\nsphere 1 0 0 0".lines());
+        let light = Light::new(Vec3::new(0., 5., 0.), Color::white(), 50.);
+        let (_, color) = scene.light_sample(&light, &Vec3::new(0., -5., 0.), 10, &mut Rng::new(0, 0));
+        let (r, g, _): (f64, f64, f64) = (&color).into();
+        assert!((r - (-1f64).exp()).abs() < 0.01 && (g - 1.).abs() < 1e-9, "{}", color);
+
+        let scene = Scene::parse("penumbra 8\nsphere 1 0 0 0".lines());
+        let (_, color) = scene.light_sample(&light, &Vec3::new(0., -5., 0.), 10, &mut Rng::new(0, 0));
+        assert!(color.is_black());
+    }
+
+    #[test]
+    fn sun_and_sky_lights() {
+        let scene = Scene::parse(
+            "fov 60\nsunlight 0 0 -1 0 1 0\nskylight 0 0 1 1 0 0\nsurface 1 1 1  0 0 0  0 0 0  1 0\nsphere 1 0 0 -4"
+                .lines(),
+        );
+        // looking straight at the sphere, the sun's behind us and the sky lights it from the side.
+        let color = scene.raycast_pixel((2, 2), 5, 5, 0).unwrap();
+        let (r, g, b): (f64, f64, f64) = (&color).into();
+        assert!((g - 1.).abs() < 0.01, "{}", color);
+        assert!((r - 0.5).abs() < 0.01 && (b - 0.5).abs() < 0.01, "{}", color);
+        assert_eq!(scene.background(&Vec3::up()), Color::new(0., 0., 1.));
+    }
+
+    #[test]
+    fn occlusion_darkens_ambient_light() {
+        let render = |settings: &str| {
+            let scene = Scene::parse(format!(
+                "fov 60\n{}\nskylight 1 1 1 1 1 1\nsurface 1 1 1  0.5 0.5 0.5  0 0 0  1 0\nsphere 1 0 0 -4\nsphere 0.5 1.2 0 -3",
+                settings,
+            ).lines());
+            (0..25).map(|i| scene.raycast_pixel((i % 5, i / 5), 5, 5, 0)).collect::<Vec<_>>()
+        };
+        let flat = render("");
+        let occluded = render("occlusion 5 1 1");
+        assert!(occluded != flat);
+        for (a, b) in flat.iter().zip(&occluded) {
+            if let (Some(a), Some(b)) = (a, b) {
+                let (r, _, _): (f64, f64, f64) = (&(a - b)).into();
+                assert!(r >= 0., "{} vs {}", a, b);
+            }
+        }
+        assert!(render("occlusion 5 1 1\nocclusion off") == flat);
+
+        // without any ambient light, there's nothing to occlude, and the area light still gets
+        // the same random points.
+        let render = |settings: &str| {
+            let scene = Scene::parse(format!(
+                "fov 60\n{}\nspherelight 1 0 5 0 1 1 1\nsurface 1 1 1  0 0 0  0 0 0  1 0\nsphere 1 0 0 -4\nsphere 0.5 1.2 0 -3",
+                settings,
+            ).lines());
+            (0..25).map(|i| scene.raycast_pixel((i % 5, i / 5), 5, 5, 0)).collect::<Vec<_>>()
+        };
+        assert!(render("occlusion 5 1 1 16") == render(""));
+    }
+
+    #[test]
+    fn later_passes_jitter() {
+        let scene = Scene::parse("fov 60\nlight 0 5 5 1 1 1\nsphere 1 0 0 -4".lines());
+        // the edge of the sphere, where moving around in the pixel changes what's seen.
+        let passes = (0..8).map(|pass| scene.raycast_pixel((3, 2), 10, 5, pass)).collect::<Vec<_>>();
+        assert!(passes.iter().skip(1).any(|color| color != &passes[0]));
+        assert_eq!(scene.passes(), 16);
+        assert_eq!(Scene::parse("integrator path 4".lines()).passes(), 1024);
+    }
+
+    #[test]
+    fn baked_voxels_keep_their_materials() {
+        use crate::grid::{Interpolation, VoxelGrid};
+        use crate::linear::Bounds;
+        use crate::mat::Material;
+        use crate::sdf::{SDF, Sphere};
+
+        let mut red = Material::new();
+        red.diffuse = Color::new(1., 0., 0.);
+        let grid = VoxelGrid::bake(
+            &Sphere::new(1.).shaded(red),
+            Bounds::centered(Vec3::new(1.5, 1.5, 1.5)),
+            (8, 8, 8),
+            Interpolation::Trilinear,
+        ).unwrap();
+        let path = std::env::temp_dir().join("gwendr_baked_voxels.gvox");
+        grid.save(path.to_str().unwrap()).unwrap();
+        let scene = Scene::parse(format!(
+            "surface 0 0 1  0 0 0  0 0 0  1 0\nvoxels {} trilinear 0 0 0",
+            path.display(),
+        ).lines());
+        let material = scene.sdf.material(&Vec3::new(1., 0., 0.)).unwrap();
+        assert_eq!(material.diffuse, Color::new(1., 0., 0.));
+    }
+
+    #[test]
+    fn files_handed_over_with_the_scene() {
+        use std::collections::HashMap;
+
+        let mut files = HashMap::new();
+        files.insert("triangle.obj".to_string(), b"v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3".to_vec());
+        let scene = Scene::parse_with_files("mesh triangle.obj 1 0 0 0".lines(), &files);
+        assert!((scene.sdf.distance(&Vec3::new(0.25, 0.25, 2.)) - 2.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn heightfield_bands() {
+        use std::collections::HashMap;
+
+        let mut files = HashMap::new();
+        files.insert("ramp.pgm".to_string(), b"P2\n3 2\n4\n0 2 4\n0 2 4\n".to_vec());
+        // white above 1.5 units up, and gray below.
+        let scene = Scene::parse_with_files(
+            "surface 1 1 1  0 0 0  0 0 0  1 0\nband 1.5 10\nsurface 0.5 0.5 0.5  0 0 0  0 0 0  1 0\nheightfield ramp.pgm 2 2 1"
+                .lines(),
+            &files,
+        );
+        let diffuse = |x: f64| scene.sdf.material(&Vec3::new(x, 0., 0.)).unwrap().diffuse;
+        assert_eq!(diffuse(-0.9), Color::new(0.5, 0.5, 0.5));
+        assert_eq!(diffuse(0.9), Color::white());
+    }
+
+    #[test]
+    fn polygons_keep_their_orientation() {
+        // a floor the way older scenes wrote it, which has always faced up, with its slab below.
+        let scene = Scene::parse(
+            "begin\nvertex -1 0 -1\nvertex 1 0 -1\nvertex 1 0 1\nvertex -1 0 1\nend".lines(),
+        );
+        assert!(scene.sdf.normal(&Vec3::new(0., 1., 0.)).dist(&Vec3::up()) < 0.0001);
+        assert!(scene.sdf.distance(&Vec3::new(0., -0.05, 0.)) < 0.);
+        assert!(scene.sdf.distance(&Vec3::new(0., 0.05, 0.)) > 0.);
+    }
+
+    #[test]
+    fn modifier_commands() {
+        let distance = |source: &str, x: f64, y: f64, z: f64| {
+            Scene::parse(source.lines()).sdf.distance(&Vec3::new(x, y, z))
+        };
+        assert!(distance("sphere 0.5 0 0 0\nrepeat 3 0 0", 6., 0., 0.).abs() < 0.5 + 1e-9);
+        assert!((distance("sphere 0.5 0 0 0\nrepeat 3 0 0 2 1 1", 6., 0., 0.) - 2.5).abs() < 1e-9);
+        assert!(distance("sphere 0.5 2 0 0\npolarrepeat 0 1 0 4", 0., 0., 2.) < 1e-9);
+        assert!((distance("sphere 1 0 0 0\nround 0.5", 2., 0., 0.) - 0.5).abs() < 1e-9);
+        assert!((distance("sphere 1 0 0 0\nshell 0.1", 0., 0., 0.) - 0.9).abs() < 1e-9);
+        assert!(distance("sphere 1 0 0 0\nelongate 1 0 0", 1.5, 0., 0.) < 0.);
+        assert!(distance("sphere 0.5 2 0 0\nmirror 1 0 0 0", -2., 0., 0.) < 0.);
+        assert!(distance("sphere 0.5 2 0 2\nsymmetric xz", -2., 0., -2.) < 0.);
+        assert!(distance("box 2 1 1 0 0 0\ntwist 0.5 1", 0., 0.4, 0.) < 0.);
+        assert!(distance("box 2 1 1 0 0 0\nbend 0.1 1", 0., 0., 0.) < 0.);
+        assert!(distance("box 1 2 1 0 0 0\ntaper 0.4 1 1", 0.6, 0.9, 0.) < 0.);
+        assert!(distance("revolve circle 0.25 1 0 0 0", 1., 0., 0.) < 0.);
+        assert!(distance("revolve rect 0.5 0.5 1 0 0 0", 0., 0., 1.) < 0.);
+        assert!(distance("sphere 1 0 0 0\ndisplace 0.1 perlin 3 2 fbm 3 0.5", 0., 0., 0.) < 0.);
+
+        // the smooth ones blend the last two objects.
+        let spheres = "sphere 1 0 0 0\nsphere 1 1 0 0\n";
+        for kernel in &["exp 32", "poly 0.1", "pow 8", "chamfer 0.1", "stairs 0.2 3"] {
+            let both = distance(&format!("{}smoothintersect {}", spheres, kernel), 0.5, 0., 0.);
+            let first = distance(&format!("{}smoothdifference {}", spheres, kernel), -0.5, 0., 0.);
+            let either = distance(&format!("{}smoothunion {}", spheres, kernel), 1.5, 0., 0.);
+            assert!(both < 0. && first < 0. && either < 0., "{}", kernel);
+            assert!(distance(&format!("{}smoothdifference {}", spheres, kernel), 1.5, 0., 0.) > 0.);
+        }
+    }
+
+    #[test]
+    fn textured_and_baked() {
+        let scene = Scene::parse(
+            "surface 0 0 0  0 0 0  0 0 0  1 0\nsphere 1 0 0 0\ntextured 1 1 1 worley 1 2".lines(),
+        );
+        let colors: Vec<Color> = (0..20)
+            .map(|i| scene.sdf.material(&Vec3::new(1., i as f64 * 0.1, 0.)).unwrap().diffuse)
+            .collect();
+        assert!(colors.iter().any(|c| c != &colors[0]));
+
+        let scene = Scene::parse(
+            "surface 1 0 0  0 0 0  0 0 0  1 0\nsphere 1 0 0 0\nbake 24 trilinear".lines(),
+        );
+        assert!(scene.sdf.distance(&Vec3::zero()) < -0.9);
+        assert!((scene.sdf.distance(&Vec3::new(0.5, 0., 0.)) + 0.5).abs() < 0.05);
+        assert_eq!(scene.sdf.material(&Vec3::new(1., 0., 0.)).unwrap().diffuse, Color::new(1., 0., 0.));
+    }
+
+    #[test]
+    fn single_samples() {
+        let mut scene = Scene::parse("fov 60\nlight 0 5 5 1 1 1\nsphere 1 0 0 -4".lines());
+        scene.seed = 3;
+        assert_eq!(scene.sample_pixel((2, 2), 5, 5, 0), scene.probe_pixel((2, 2), 5, 5, 0).color);
+        // later passes look elsewhere in the pixel.
+        let colors: Vec<_> = (1..5).map(|pass| scene.sample_pixel((2, 2), 5, 5, pass)).collect();
+        assert!(colors.iter().all(|color| color.is_some()));
+        assert!(colors.iter().any(|color| color != &colors[0]));
+    }
+
+    #[test]
+    fn reproducible_renders() {
+        let render = |seed| {
+            let mut scene = Scene::parse("fov 60\nlight 0 5 5 1 1 1\nsphere 1 0 0 -4".lines());
+            scene.seed = seed;
+            (0..25).map(|i| scene.raycast_pixel((i % 5, i / 5), 5, 5, 3)).collect::<Vec<_>>()
+        };
+        let image = render(7);
+        assert!(image[12].is_some());
+        assert!(image == render(7));
+    }
 }
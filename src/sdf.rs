@@ -1,7 +1,8 @@
 use crate::linear::*;
 use crate::mat::Material;
+use crate::noise::{Noise, TexturedSDF};
+use crate::sdf2::{Polygon, SDF2};
 use wasm_bindgen::__rt::core::f64::consts::PI;
-use crate::log;
 
 const MAX_FLOAT: f64 = (1u64 << 53u64) as f64;
 
@@ -47,6 +48,11 @@ pub trait SDF {
         None
     }
 
+    /// A box containing everything inside the shape, if it's finite.
+    fn bounds(&self) -> Option<Bounds> {
+        None
+    }
+
     fn negate(self) -> NegationSDF where Self: Sized + 'static {
         NegationSDF { sdf: Box::new(self) }
     }
@@ -59,6 +65,16 @@ pub trait SDF {
         SmoothUnionSDF::new(Box::new(self), sdf, s)
     }
 
+    fn smooth_intersection(self, sdf: Box<dyn SDF>, s: Option<SmoothUnionType>) -> SmoothIntersectionSDF
+        where Self: Sized + 'static {
+        SmoothIntersectionSDF::new(Box::new(self), sdf, s)
+    }
+
+    fn smooth_difference(self, sdf: Box<dyn SDF>, s: Option<SmoothUnionType>) -> SmoothDifferenceSDF
+        where Self: Sized + 'static {
+        SmoothDifferenceSDF::new(Box::new(self), sdf, s)
+    }
+
     fn intersection(self, sdf: Box<dyn SDF>) -> IntersectionSDF where Self: Sized + 'static {
         IntersectionSDF { a: Box::new(self), b: sdf }
     }
@@ -86,9 +102,91 @@ pub trait SDF {
         }
     }
 
-    fn transformed(self, func: Box<dyn Fn(&Vec3, &Box<dyn SDF>) -> f64>) -> TransformedSDF
+    /// Warps space by looking up each point's distance at `func(point)` instead. `lipschitz` is
+    /// the most `func` stretches distances by, which the distance is divided by so steps don't
+    /// overshoot the warped surface.
+    fn transformed(self, func: Box<dyn Fn(&Vec3) -> Vec3>, lipschitz: f64) -> TransformedSDF
+        where Self: Sized + 'static {
+        TransformedSDF::new(Box::new(self), func, lipschitz)
+    }
+
+    /// Infinitely repeats this shape on a grid with the given spacing. A zero component means
+    /// no repetition along that axis, so e.g. `Vec3::new(2., 0., 0.)` repeats along x only.
+    fn repeat(self, spacing: Vec3) -> RepeatSDF where Self: Sized + 'static {
+        RepeatSDF::new(Box::new(self), spacing, None)
+    }
+
+    /// Like `repeat`, but only places `count` copies along each axis, starting at the origin
+    /// and going in the direction of the spacing.
+    fn repeat_limited(self, spacing: Vec3, count: (usize, usize, usize)) -> RepeatSDF
         where Self: Sized + 'static {
-        TransformedSDF::new(Box::new(self), func)
+        RepeatSDF::new(Box::new(self), spacing, Some(count))
+    }
+
+    /// Repeats this shape `count` times around the given axis (through the origin).
+    fn repeat_polar(self, axis: Vec3, count: usize) -> PolarRepeatSDF where Self: Sized + 'static {
+        PolarRepeatSDF::new(Box::new(self), axis, count)
+    }
+
+    /// Twists the shape around the y axis by `rate` radians per unit of height. `radius` is how
+    /// far the shape extends from the y axis, which bounds how much the twist stretches space.
+    fn twist(self, rate: f64, radius: f64) -> TwistSDF where Self: Sized + 'static {
+        TwistSDF::new(Box::new(self), rate, radius)
+    }
+
+    /// Bends the x axis into an arc in the xy plane (curving toward +y for positive rates).
+    /// `radius` is how far the shape extends from the z axis.
+    fn bend(self, rate: f64, radius: f64) -> BendSDF where Self: Sized + 'static {
+        BendSDF::new(Box::new(self), rate, radius)
+    }
+
+    /// Scales the xz cross-section by `1 + rate * y`, for y within `[-height, height]`.
+    /// `radius` is how far the shape extends from the y axis.
+    fn taper(self, rate: f64, height: f64, radius: f64) -> TaperSDF where Self: Sized + 'static {
+        TaperSDF::new(Box::new(self), rate, height, radius)
+    }
+
+    /// Adds the displacement to the distance. This assumes the shape's own distance changes by
+    /// at most 1 per unit of distance, so the sum changes by at most `1 + displacement.lipschitz()`,
+    /// and the distance is divided by that to keep steps from overshooting.
+    fn displace<D>(self, displacement: D) -> DisplacedSDF
+        where Self: Sized + 'static, D: Displacement + 'static {
+        DisplacedSDF::new(Box::new(self), Box::new(displacement))
+    }
+
+    /// Blends between two materials across the surface, following the noise.
+    fn textured<N>(self, noise: N, low: Material, high: Material) -> TexturedSDF
+        where Self: Sized + 'static, N: Noise + 'static {
+        TexturedSDF::new(Box::new(self), Box::new(noise), low, high)
+    }
+
+    /// Inflates the shape by `radius`, rounding off its edges.
+    fn round(self, radius: f64) -> RoundedSDF where Self: Sized + 'static {
+        RoundedSDF { sdf: Box::new(self), radius }
+    }
+
+    /// Hollows out the shape, leaving a shell of the given thickness just inside its surface.
+    /// The thickness has to be positive, since a shell with none has no surface to hit.
+    fn shell(self, thickness: f64) -> ShellSDF where Self: Sized + 'static {
+        assert!(thickness > 0., "shell thickness has to be positive, got {}", thickness);
+        ShellSDF { sdf: Box::new(self), thickness }
+    }
+
+    /// Pulls the halves of the shape apart by `2 * half_length` along each axis, filling in the
+    /// gap with its cross-section.
+    fn elongate(self, half_length: Vec3) -> ElongatedSDF where Self: Sized + 'static {
+        ElongatedSDF { sdf: Box::new(self), half_length }
+    }
+
+    /// Reflects the half of space behind the plane (with the given normal, `offset` units from
+    /// the origin) onto the half in front of it, so both halves show what's in front.
+    fn mirror(self, normal: Vec3, offset: f64) -> MirrorSDF where Self: Sized + 'static {
+        MirrorSDF { sdf: Box::new(self), normal: normal.normalize(), offset }
+    }
+
+    /// Mirrors the positive side of each of the chosen axes onto the negative side.
+    fn symmetric(self, axes: (bool, bool, bool)) -> SymmetricSDF where Self: Sized + 'static {
+        SymmetricSDF { sdf: Box::new(self), axes }
     }
 
     fn raymarch(&self, ray: &Ray, far_plane: f64) -> Option<RayHit> {
@@ -117,6 +215,27 @@ pub trait SDF {
             material,
         })
     }
+
+    /// A cheap estimate of how much of a light at the end of the ray is visible, from 0 to 1,
+    /// for soft shadows with a single ray. Marching past something closely, relative to how far
+    /// along the ray we are, means it's probably hiding part of the light. Bigger `k` gives
+    /// sharper shadows.
+    fn soft_shadow(&self, ray: &Ray, far_plane: f64, k: f64) -> f64 {
+        // https://iquilezles.org/articles/rmshadows/
+        let direction = ray.direction.clone().normalize();
+        let epsilon = self.epsilon();
+        let mut visible: f64 = 1.;
+        let mut t = epsilon;
+        while t < far_plane {
+            let distance = self.distance(&ray.origin.clone().add(t, &direction));
+            if distance < epsilon {
+                return 0.;
+            }
+            visible = visible.min(k * distance / t);
+            t += distance;
+        }
+        visible.clamp(0., 1.)
+    }
 }
 
 #[derive(Debug)]
@@ -143,7 +262,7 @@ impl SDF for SdfShape {
         self.epsilon
     }
 
-    fn material(&self, point: &Vec3) -> Option<Material> {
+    fn material(&self, _point: &Vec3) -> Option<Material> {
         self.mat.clone()
     }
 }
@@ -164,12 +283,75 @@ pub struct Disk {
     radius: f64,
 }
 
+/// A flat polygon, which may be concave, thickened into a slab behind its front face. Zero
+/// thickness gives a two-sided sheet with no inside.
 #[derive(Clone)]
 pub struct PolyFace {
-    normal: Vec3,
-    centroid: Vec3,
-    vertices: Vec<Vec3>,
-    epsilon: f64,
+    /// origin at the centroid, with k along the normal.
+    frame: Frame,
+    outline: Polygon,
+    thickness: f64,
+}
+
+/// A box with the given size, centered on the origin.
+#[derive(Clone)]
+pub struct Cuboid {
+    half_size: Vec3,
+}
+
+/// A box with its edges rounded off to the given radius, without changing its size.
+#[derive(Clone)]
+pub struct RoundedCuboid {
+    half_size: Vec3,
+    radius: f64,
+}
+
+/// A donut lying in the xz plane.
+#[derive(Clone)]
+pub struct Torus {
+    major_radius: f64,
+    minor_radius: f64,
+}
+
+/// A line segment, thickened by a radius (which may be zero).
+#[derive(Clone)]
+pub struct Capsule {
+    a: Vec3,
+    b: Vec3,
+    radius: f64,
+}
+
+/// A capped cylinder along the y axis, centered on the origin.
+#[derive(Clone)]
+pub struct Cylinder {
+    radius: f64,
+    half_height: f64,
+}
+
+/// A capped cone along the y axis, centered on the origin.
+#[derive(Clone)]
+pub struct Cone {
+    half_height: f64,
+    bottom_radius: f64,
+    top_radius: f64,
+}
+
+#[derive(Clone)]
+pub struct Ellipsoid {
+    radii: Vec3,
+}
+
+/// A hexagonal prism along the y axis, where the radius is the distance to the flat sides.
+#[derive(Clone)]
+pub struct HexPrism {
+    radius: f64,
+    half_height: f64,
+}
+
+/// A regular octahedron, where the size is the distance from the center to each vertex.
+#[derive(Clone)]
+pub struct Octahedron {
+    size: f64,
 }
 
 #[derive(Clone)]
@@ -187,6 +369,18 @@ impl SmoothUnionSDF {
     }
 }
 
+impl SmoothIntersectionSDF {
+    pub fn new(a: Box<dyn SDF>, b: Box<dyn SDF>, k: Option<SmoothUnionType>) -> Self {
+        Self { a, b, smooth: k.unwrap_or(SmoothUnionType::Exp(32.)) }
+    }
+}
+
+impl SmoothDifferenceSDF {
+    pub fn new(a: Box<dyn SDF>, b: Box<dyn SDF>, k: Option<SmoothUnionType>) -> Self {
+        Self { a, b, smooth: k.unwrap_or(SmoothUnionType::Exp(32.)) }
+    }
+}
+
 impl IntersectionSDF {
     pub fn new(a: Box<dyn SDF>, b: Box<dyn SDF>) -> Self {
         Self { a, b }
@@ -212,243 +406,947 @@ impl<'a> NegatedRefSDF<'a> {
 }
 
 impl TransformedSDF {
-    pub fn new(sdf: Box<dyn SDF>, func: Box<dyn Fn(&Vec3, &Box<dyn SDF>) -> f64>) -> Self {
-        Self { sdf, func }
+    pub fn new(sdf: Box<dyn SDF>, func: Box<dyn Fn(&Vec3) -> Vec3>, lipschitz: f64) -> Self {
+        Self { sdf, func, lipschitz: lipschitz.max(1.) }
     }
 }
 
-impl Sphere {
-    pub fn new(radius: f64) -> Self {
-        Self { radius }
+impl RepeatSDF {
+    pub fn new(sdf: Box<dyn SDF>, spacing: Vec3, count: Option<(usize, usize, usize)>) -> Self {
+        Self { sdf, spacing, count }
+    }
+
+    /// Finds the copy closest to the point, returning its distance and the point in the
+    /// local space of that copy.
+    fn nearest_copy(&self, point: &Vec3) -> (f64, Vec3) {
+        let count = self.count.map(|(x, y, z)| (Some(x), Some(y), Some(z)))
+            .unwrap_or((None, None, None));
+        let (xs, xn) = repetition_cells(point.x, self.spacing.x, count.0);
+        let (ys, yn) = repetition_cells(point.y, self.spacing.y, count.1);
+        let (zs, zn) = repetition_cells(point.z, self.spacing.z, count.2);
+
+        // the nearest cell isn't necessarily the one containing the nearest surface if the
+        // shape pokes out of its cell, so we check the neighboring cell on each axis too.
+        let mut nearest = (MAX_FLOAT, point.clone());
+        for x in &xs[..xn] {
+            for y in &ys[..yn] {
+                for z in &zs[..zn] {
+                    let local = Vec3::new(
+                        point.x - x * self.spacing.x,
+                        point.y - y * self.spacing.y,
+                        point.z - z * self.spacing.z,
+                    );
+                    let distance = self.sdf.distance(&local);
+                    if distance < nearest.0 {
+                        nearest = (distance, local);
+                    }
+                }
+            }
+        }
+        nearest
     }
 }
 
-impl Plane {
-    pub fn new(normal: Vec3) -> Self { Self { normal } }
-}
-
-impl Disk {
-    pub fn new(normal: Vec3, radius: f64) -> Self {
-        Self {
-            normal,
-            radius,
-        }
+/// Returns the index of the repetition cell nearest to the coordinate and its neighbor on the
+/// side of the coordinate (if it exists), along with how many cells were returned.
+fn repetition_cells(coord: f64, spacing: f64, count: Option<usize>) -> ([f64; 2], usize) {
+    if spacing == 0. {
+        return ([0., 0.], 1);
+    }
+    let clamp = |cell: f64| match count {
+        Some(count) => cell.max(0.).min(count.max(1) as f64 - 1.),
+        None => cell,
+    };
+    let cell = clamp((coord / spacing).round());
+    let neighbor = clamp(cell + (coord - cell * spacing).signum() * spacing.signum());
+    if neighbor == cell {
+        ([cell, cell], 1)
+    } else {
+        ([cell, neighbor], 2)
     }
 }
 
-impl PolyFace {
-    pub fn new(vertices: Vec<Vec3>) -> Self {
-        let (centroid, normal) = if vertices.len() >= 3 {
-            let centroid = vertices.iter()
-                .fold(Vec3::zero(), |a, b| &a + b)
-                .scale(1. / (vertices.len() as f64));
-            let a = &vertices[0] - &centroid;
-            let b = &vertices[1] - &centroid;
-            (centroid, (&b ^ &a).normalize())
+impl PolarRepeatSDF {
+    pub fn new(sdf: Box<dyn SDF>, axis: Vec3, count: usize) -> Self {
+        let axis = axis.normalize();
+        // the copy at angle 0 is the one centered on this direction.
+        let reference = Vec3::right().off_axis(&axis);
+        let reference = if reference.norm2() < 0.0001 {
+            Vec3::forward().off_axis(&axis)
         } else {
-            (Vec3::zero(), Vec3::zero())
-        };
-        let epsilon = {
-            let mut eps: f64 = 1.;
-            for i in 0..vertices.len() {
-                let d = vertices[i].dist(&vertices[(i + 1) % vertices.len()]);
-                eps = eps.min(d / 1000.);
+            reference
+        }.normalize();
+        Self { sdf, axis, count: count.max(1), reference }
+    }
+
+    fn nearest_copy(&self, point: &Vec3) -> (f64, Vec3) {
+        let sector = 2. * PI / (self.count as f64);
+        let binormal = &self.axis ^ &self.reference;
+        let angle = (point * &binormal).atan2(point * &self.reference);
+        let index = (angle / sector).round();
+        let neighbor = index + (angle - index * sector).signum();
+
+        let mut nearest = (MAX_FLOAT, point.clone());
+        let (copies, n) = if self.count > 1 { ([index, neighbor], 2) } else { ([0., 0.], 1) };
+        for copy in &copies[..n] {
+            let local = point.clone().rotate(-copy * sector, &self.axis);
+            let distance = self.sdf.distance(&local);
+            if distance < nearest.0 {
+                nearest = (distance, local);
             }
-            eps
-        };
-        Self {
-            normal,
-            centroid,
-            vertices,
-            epsilon,
         }
+        nearest
     }
 }
 
-impl SDF for Sphere {
-    fn distance(&self, point: &Vec3) -> f64 {
-        point.norm() - self.radius
+/// Lipschitz constant of a map that rotates space at `shear` radians per unit distance, or
+/// shifts it by at most `shear` units per unit distance (i.e. the largest singular value of
+/// its jacobian).
+pub fn shear_lipschitz(shear: f64) -> f64 {
+    let shear = shear.abs();
+    (shear + (shear * shear + 4.).sqrt()) / 2.
+}
+
+impl TwistSDF {
+    pub fn new(sdf: Box<dyn SDF>, rate: f64, radius: f64) -> Self {
+        Self { sdf, rate, radius }
     }
 
-    fn epsilon(&self) -> f64 {
-        self.radius / 1_000.0
+    /// The most the twisted distance can change per unit of distance around this point.
+    pub fn lipschitz(&self, point: &Vec3) -> f64 {
+        let r = (point.x * point.x + point.z * point.z).sqrt();
+        shear_lipschitz(self.rate * r.max(self.radius))
+    }
+
+    fn untwist(&self, point: &Vec3) -> Vec3 {
+        point.clone().rotate(-self.rate * point.y, &Vec3::up())
     }
 }
 
-impl SDF for Plane {
-    fn distance(&self, point: &Vec3) -> f64 {
-        self.normal.dot(point)
+impl BendSDF {
+    pub fn new(sdf: Box<dyn SDF>, rate: f64, radius: f64) -> Self {
+        Self { sdf, rate, radius }
     }
 
-    fn epsilon(&self) -> f64 {
-        0.001
+    /// The most the bent distance can change per unit of distance around this point.
+    pub fn lipschitz(&self, point: &Vec3) -> f64 {
+        let r = (point.x * point.x + point.y * point.y).sqrt();
+        shear_lipschitz(self.rate * r.max(self.radius))
+    }
+
+    fn unbend(&self, point: &Vec3) -> Vec3 {
+        point.clone().rotate(-self.rate * point.x, &Vec3::forward())
     }
 }
 
-impl SDF for Disk {
-    fn distance(&self, point: &Vec3) -> f64 {
-        // this is really just the intersection of a sphere and an infinite plane
-        self.normal.dot(point).max(point.norm() - self.radius)
+impl TaperSDF {
+    pub fn new(sdf: Box<dyn SDF>, rate: f64, height: f64, radius: f64) -> Self {
+        assert!(
+            1. - rate.abs() * height > 0.,
+            "taper rate {} would collapse the shape within height {}", rate, height,
+        );
+        Self { sdf, rate, height, radius }
     }
 
-    fn epsilon(&self) -> f64 {
-        self.radius / 1_000.0
+    /// The most the tapered distance can change per unit of distance around this point.
+    pub fn lipschitz(&self, point: &Vec3) -> f64 {
+        let r = (point.x * point.x + point.z * point.z).sqrt().max(self.radius);
+        let min_scale = 1. - self.rate.abs() * self.height;
+        (1. / min_scale).max(1.) + self.rate.abs() * r / (min_scale * min_scale)
+    }
+
+    fn untaper(&self, point: &Vec3) -> Vec3 {
+        let scale = 1. + self.rate * point.y.max(-self.height).min(self.height);
+        Vec3::new(point.x / scale, point.y, point.z / scale)
     }
 }
 
-impl SDF for EmptySDF {
-    fn distance(&self, _: &Vec3) -> f64 {
-        MAX_FLOAT
+impl DisplacedSDF {
+    pub fn new(sdf: Box<dyn SDF>, displacement: Box<dyn Displacement>) -> Self {
+        Self { sdf, displacement }
     }
 
-    fn epsilon(&self) -> f64 {
-        1.
+    /// The most the displaced distance can change per unit of distance.
+    pub fn lipschitz(&self) -> f64 {
+        1. + self.displacement.lipschitz()
     }
 }
 
-impl SDF for PolyFace {
-    fn distance(&self, point: &Vec3) -> f64 {
-        if self.vertices.len() < 3 {
-            return MAX_FLOAT;
-        }
+impl Sphere {
+    pub fn new(radius: f64) -> Self {
+        Self { radius }
+    }
+}
 
-        let thickness = 0.1;
+impl Plane {
+    pub fn new(normal: Vec3) -> Self { Self { normal } }
+}
 
-        let mut sd = self.normal.dot(&(point - &self.centroid));
-        sd = sd.max(-self.normal.dot(&(point - &self.centroid)) - thickness);
-        for i in 0..self.vertices.len() {
-            let a = &self.vertices[i];
-            let b = &self.vertices[(i + 1) % self.vertices.len()];
-            let edge_normal = (b - a).rotate(PI / 2.0, &self.normal).normalize();
-            sd = sd.max(&(point - a) * &edge_normal - self.epsilon)
+impl Disk {
+    pub fn new(normal: Vec3, radius: f64) -> Self {
+        Self {
+            normal,
+            radius,
         }
-        sd
     }
+}
 
-    fn normal(&self, _: &Vec3) -> Vec3 {
-        self.normal.clone()
+impl PolyFace {
+    /// Vertices go clockwise around the front face, as seen from in front of it. If they aren't
+    /// coplanar, they're flattened onto the plane that best fits them.
+    pub fn new(vertices: Vec<Vec3>, thickness: f64) -> Result<Self, String> {
+        if vertices.len() < 3 {
+            return Err(format!("polygon needs at least 3 vertices, got {}", vertices.len()));
+        }
+        let centroid = vertices.iter()
+            .fold(Vec3::zero(), |a, b| &a + b)
+            .scale(1. / (vertices.len() as f64));
+
+        // Newell's method, which is robust to concavity and nearly collinear vertices.
+        let mut normal = Vec3::zero();
+        for i in 0..vertices.len() {
+            let a = &vertices[i];
+            let b = &vertices[(i + 1) % vertices.len()];
+            normal = normal.add(1., &Vec3::new(
+                (a.y - b.y) * (a.z + b.z),
+                (a.z - b.z) * (a.x + b.x),
+                (a.x - b.x) * (a.y + b.y),
+            ));
+        }
+        if normal.norm2() == 0. {
+            return Err("polygon has no area".to_string());
+        }
+        // Newell's normal faces the side the vertices go counter-clockwise around, but polygons
+        // have always faced the other way, so scenes written for that keep working.
+        let normal = normal.normalize().scale(-1.);
+
+        let helper = if normal.x.abs() < 0.9 { Vec3::right() } else { Vec3::up() };
+        let i = (&helper ^ &normal).normalize();
+        let j = &normal ^ &i;
+        let frame = Frame::new(centroid, i, j, normal);
+        let outline = Polygon::new(vertices.iter()
+            .map(|v| {
+                let local = frame.unproject_point(v);
+                Vec2::new(local.x, local.y)
+            })
+            .collect());
+        Ok(Self { frame, outline, thickness: thickness.max(0.) })
+    }
+
+    /// In-plane distance to the outline and height above the middle of the slab.
+    fn offsets(&self, point: &Vec3) -> (Vec2, f64) {
+        let local = self.frame.unproject_point(point);
+        let flat = Vec2::new(local.x, local.y);
+        (flat, local.z + self.thickness / 2.)
     }
+}
 
-
-    fn epsilon(&self) -> f64 {
-        self.epsilon
+impl Cuboid {
+    pub fn new(size: Vec3) -> Self {
+        Self { half_size: size.scale(0.5) }
     }
 }
 
-pub struct MatSDF {
-    sdf: Box<dyn SDF>,
-    mat: Material,
-}
+impl RoundedCuboid {
+    pub fn new(size: Vec3, radius: f64) -> Self {
+        let half_size = size.scale(0.5);
+        let radius = radius.min(half_size.x).min(half_size.y).min(half_size.z);
+        Self { half_size, radius }
+    }
 
-pub struct DynFuncSdf {
-    func: Box<dyn Fn(&Vec3) -> f64>,
-    epsilon: f64,
+    fn core(&self) -> Vec3 {
+        self.half_size.clone().add(-self.radius, &Vec3::new(1., 1., 1.))
+    }
 }
 
-pub struct FuncSdf {
-    func: fn(&Vec3) -> f64,
-    epsilon: f64,
+impl Torus {
+    pub fn new(major_radius: f64, minor_radius: f64) -> Self {
+        Self { major_radius, minor_radius }
+    }
 }
 
-pub struct UnionSDF {
-    a: Box<dyn SDF>,
-    b: Box<dyn SDF>,
-}
+impl Capsule {
+    pub fn new(a: Vec3, b: Vec3, radius: f64) -> Self {
+        Self { a, b, radius }
+    }
 
-pub struct SmoothUnionSDF {
-    a: Box<dyn SDF>,
-    b: Box<dyn SDF>,
-    smooth: SmoothUnionType,
+    fn closest_point(&self, point: &Vec3) -> Vec3 {
+        let ab = &self.b - &self.a;
+        let t = if ab.norm2() > 0. {
+            (&(point - &self.a) * &ab / ab.norm2()).clamp(0., 1.)
+        } else {
+            0.
+        };
+        self.a.clone().add(t, &ab)
+    }
 }
 
-pub enum SmoothUnionType {
-    /** exponential smoothing, default parameter = 32 */
-    Exp(f64),
-    /** polynomial smoothing, default parameter = 0.1 */
-    Poly(f64),
-    /** power smoothing, default parameter = 8 */
-    Pow(f64),
+impl Cylinder {
+    pub fn new(radius: f64, height: f64) -> Self {
+        Self { radius, half_height: height / 2. }
+    }
 }
 
-pub struct IntersectionSDF {
-    a: Box<dyn SDF>,
-    b: Box<dyn SDF>,
+impl Cone {
+    pub fn new(height: f64, bottom_radius: f64, top_radius: f64) -> Self {
+        Self { half_height: height / 2., bottom_radius, top_radius }
+    }
+
+    /// Vector from the closest point on the cone's profile to the point (in the plane of
+    /// distance-from-axis and height), along with the signed distance.
+    fn profile_offset(&self, point: &Vec3) -> (Vec2, f64) {
+        // https://iquilezles.org/www/articles/distfunctions/distfunctions.htm
+        let (h, r1, r2) = (self.half_height, self.bottom_radius, self.top_radius);
+        let q = Vec2::new((point.x * point.x + point.z * point.z).sqrt(), point.y);
+        let k1 = Vec2::new(r2, h);
+        let k2 = Vec2::new(r2 - r1, 2. * h);
+        let cap_radius = if q.y < 0. { r1 } else { r2 };
+        let cap_height = q.y.abs() - h;
+        let to_cap = Vec2::new(q.x - q.x.min(cap_radius), cap_height * q.y.signum());
+        let to_side = (&q - &k1).add((&(&k1 - &q) * &k2 / k2.norm2()).clamp(0., 1.), &k2);
+        let sign = if to_side.x < 0. && cap_height < 0. { -1. } else { 1. };
+        let offset = if to_cap.norm2() < to_side.norm2() { to_cap } else { to_side };
+        let distance = sign * offset.norm();
+        (offset, distance)
+    }
 }
 
+impl Ellipsoid {
+    pub fn new(radii: Vec3) -> Self {
+        Self { radii }
+    }
 
-pub struct DifferenceSDF {
-    a: Box<dyn SDF>,
-    b: Box<dyn SDF>,
+    fn min_radius(&self) -> f64 {
+        self.radii.x.min(self.radii.y).min(self.radii.z)
+    }
 }
 
-pub struct NegationSDF {
-    sdf: Box<dyn SDF>,
+impl HexPrism {
+    pub fn new(radius: f64, height: f64) -> Self {
+        Self { radius, half_height: height / 2. }
+    }
+
+    /// Signed distance to the hexagonal cross-section in the xz plane, with its gradient.
+    fn hexagon(&self, point: &Vec3) -> (f64, Vec2) {
+        // https://iquilezles.org/www/articles/distfunctions2d/distfunctions2d.htm
+        let k = Vec2::new(-(3f64.sqrt()) / 2., 0.5);
+        let half_side = self.radius / 3f64.sqrt();
+        let q = Vec2::new(point.x.abs(), point.z.abs());
+        let fold = (&k * &q).min(0.);
+        let q = q.add(-2. * fold, &k);
+        let offset = Vec2::new(q.x - q.x.clamp(-half_side, half_side), q.y - self.radius);
+        let distance = offset.norm() * offset.y.signum();
+        let g = if distance == 0. { Vec2::new(0., 1.) } else { offset.scale(1. / distance) };
+        let g = g.clone().add(-2. * (&k * &g) * (fold < 0.) as u8 as f64, &k);
+        (distance, Vec2::new(g.x * point.x.signum(), g.y * point.z.signum()))
+    }
 }
 
-pub struct NegatedRefSDF<'a> {
-    sdf: &'a Box<dyn SDF>,
+impl Octahedron {
+    pub fn new(size: f64) -> Self {
+        Self { size }
+    }
+
+    /// Signed distance to the surface, along with the closest point on it.
+    fn nearest(&self, point: &Vec3) -> (f64, Vec3) {
+        // by symmetry, the closest point is on the face in the same octant as the point.
+        let signs = Vec3::new(point.x.signum(), point.y.signum(), point.z.signum());
+        let q = Vec3::new(point.x.abs(), point.y.abs(), point.z.abs());
+        let closest = closest_point_on_triangle(
+            &q,
+            &Vec3::new(self.size, 0., 0.),
+            &Vec3::new(0., self.size, 0.),
+            &Vec3::new(0., 0., self.size),
+        );
+        let sign = (q.x + q.y + q.z - self.size).signum();
+        (sign * q.dist(&closest), closest.scale_vec(&signs))
+    }
 }
 
-pub struct TranslatedSDF {
-    sdf: Box<dyn SDF>,
-    translation: Vec3,
+/// Distance to a prism whose cross-section is `flat` units away, and which is capped at
+/// `half_height` above and below the xz plane.
+fn prism_distance(flat: f64, y: f64, half_height: f64) -> f64 {
+    let w = Vec2::new(flat, y.abs() - half_height);
+    w.x.max(w.y).min(0.) + Vec2::new(w.x.max(0.), w.y.max(0.)).norm()
 }
 
-pub struct ScaledSDF {
-    sdf: Box<dyn SDF>,
-    scale: f64,
+/// Normal of a prism, given the cross-section's distance and gradient (in the xz plane).
+fn prism_normal(flat: f64, gradient: Vec3, y: f64, half_height: f64) -> Vec3 {
+    let cap_distance = y.abs() - half_height;
+    let cap = Vec3::new(0., y.signum(), 0.);
+    if flat > 0. && cap_distance > 0. {
+        gradient.scale(flat).add(cap_distance, &cap).normalize()
+    } else if flat > cap_distance {
+        gradient
+    } else {
+        cap
+    }
 }
 
-pub struct RotatedSDF {
-    sdf: Box<dyn SDF>,
-    angle: f64,
-    axis: Vec3,
+fn cuboid_distance(point: &Vec3, half_size: &Vec3) -> f64 {
+    let q = Vec3::new(
+        point.x.abs() - half_size.x,
+        point.y.abs() - half_size.y,
+        point.z.abs() - half_size.z,
+    );
+    Vec3::new(q.x.max(0.), q.y.max(0.), q.z.max(0.)).norm() + q.x.max(q.y).max(q.z).min(0.)
 }
 
-pub struct TransformedSDF {
-    sdf: Box<dyn SDF>,
-    func: Box<dyn Fn(&Vec3, &Box<dyn SDF>) -> f64>,
+fn cuboid_normal(point: &Vec3, half_size: &Vec3) -> Vec3 {
+    let q = Vec3::new(
+        point.x.abs() - half_size.x,
+        point.y.abs() - half_size.y,
+        point.z.abs() - half_size.z,
+    );
+    let n = if q.x > 0. || q.y > 0. || q.z > 0. {
+        Vec3::new(q.x.max(0.), q.y.max(0.), q.z.max(0.)).normalize()
+    } else if q.x > q.y && q.x > q.z {
+        Vec3::right()
+    } else if q.y > q.z {
+        Vec3::up()
+    } else {
+        Vec3::forward()
+    };
+    Vec3::new(n.x * point.x.signum(), n.y * point.y.signum(), n.z * point.z.signum())
 }
 
-impl SDF for MatSDF {
+/// Turns a gradient in the plane of (distance from the y axis, height) into a 3d normal.
+fn revolved_normal(point: &Vec3, gradient: &Vec2) -> Vec3 {
+    let radial = Vec3::new(point.x, 0., point.z);
+    let radial = if radial.norm2() > 0. { radial.normalize() } else { Vec3::right() };
+    radial.scale(gradient.x).add(gradient.y, &Vec3::up())
+}
+
+impl SDF for Sphere {
     fn distance(&self, point: &Vec3) -> f64 {
-        self.sdf.distance(point)
+        point.norm() - self.radius
     }
 
-    fn epsilon(&self) -> f64 {
-        self.sdf.epsilon()
+    fn normal(&self, point: &Vec3) -> Vec3 {
+        if point.norm2() == 0. {
+            return Vec3::up();
+        }
+        point.clone().normalize()
     }
 
-    fn material(&self, _: &Vec3) -> Option<Material> {
-        Some(self.mat.clone())
+    fn epsilon(&self) -> f64 {
+        self.radius / 1_000.0
     }
-}
 
-impl FuncSdf {
-    pub fn new(func: fn(&Vec3) -> f64, epsilon: f64) -> Self {
-        FuncSdf {
-            func,
-            epsilon,
-        }
+    fn bounds(&self) -> Option<Bounds> {
+        Some(Bounds::centered(Vec3::new(self.radius, self.radius, self.radius)))
     }
 }
 
-impl SDF for DynFuncSdf {
+impl SDF for Cuboid {
     fn distance(&self, point: &Vec3) -> f64 {
-        (self.func)(point)
+        cuboid_distance(point, &self.half_size)
+    }
+
+    fn normal(&self, point: &Vec3) -> Vec3 {
+        cuboid_normal(point, &self.half_size)
     }
 
     fn epsilon(&self) -> f64 {
-        self.epsilon
+        self.half_size.x.min(self.half_size.y).min(self.half_size.z) / 1_000.0
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        Some(Bounds::centered(self.half_size.clone()))
     }
 }
 
-impl SDF for UnionSDF {
+impl SDF for RoundedCuboid {
     fn distance(&self, point: &Vec3) -> f64 {
-        self.a.distance(point).min(self.b.distance(point))
+        cuboid_distance(point, &self.core()) - self.radius
     }
 
-    fn epsilon(&self) -> f64 {
+    fn normal(&self, point: &Vec3) -> Vec3 {
+        cuboid_normal(point, &self.core())
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.half_size.x.min(self.half_size.y).min(self.half_size.z) / 1_000.0
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        Some(Bounds::centered(self.half_size.clone()))
+    }
+}
+
+impl SDF for Torus {
+    fn distance(&self, point: &Vec3) -> f64 {
+        let ring = (point.x * point.x + point.z * point.z).sqrt() - self.major_radius;
+        Vec2::new(ring, point.y).norm() - self.minor_radius
+    }
+
+    fn normal(&self, point: &Vec3) -> Vec3 {
+        let ring = (point.x * point.x + point.z * point.z).sqrt() - self.major_radius;
+        let gradient = Vec2::new(ring, point.y).normalize();
+        if gradient.norm2() == 0. {
+            return Vec3::up();
+        }
+        revolved_normal(point, &gradient)
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.minor_radius / 1_000.0
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        let r = self.major_radius + self.minor_radius;
+        Some(Bounds::centered(Vec3::new(r, self.minor_radius, r)))
+    }
+}
+
+impl SDF for Capsule {
+    fn distance(&self, point: &Vec3) -> f64 {
+        point.dist(&self.closest_point(point)) - self.radius
+    }
+
+    fn normal(&self, point: &Vec3) -> Vec3 {
+        let n = point - &self.closest_point(point);
+        if n.norm2() == 0. {
+            // on the core segment itself, so any direction perpendicular to it will do.
+            let ab = &self.b - &self.a;
+            let n = Vec3::cross(&ab, &Vec3::right());
+            return if n.norm2() > 0. { n.normalize() } else { Vec3::up() };
+        }
+        n.normalize()
+    }
+
+    fn epsilon(&self) -> f64 {
+        if self.radius > 0. {
+            self.radius / 1_000.0
+        } else {
+            0.0001
+        }
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        let r = self.radius;
+        Some(Bounds::around(&[self.a.clone(), self.b.clone()]).expand(&Vec3::new(r, r, r)))
+    }
+}
+
+impl SDF for Cylinder {
+    fn distance(&self, point: &Vec3) -> f64 {
+        let flat = (point.x * point.x + point.z * point.z).sqrt() - self.radius;
+        prism_distance(flat, point.y, self.half_height)
+    }
+
+    fn normal(&self, point: &Vec3) -> Vec3 {
+        let flat = (point.x * point.x + point.z * point.z).sqrt() - self.radius;
+        let radial = revolved_normal(point, &Vec2::new(1., 0.));
+        prism_normal(flat, radial, point.y, self.half_height)
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.radius.min(self.half_height) / 1_000.0
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        Some(Bounds::centered(Vec3::new(self.radius, self.half_height, self.radius)))
+    }
+}
+
+impl SDF for Cone {
+    fn distance(&self, point: &Vec3) -> f64 {
+        self.profile_offset(point).1
+    }
+
+    fn normal(&self, point: &Vec3) -> Vec3 {
+        let (offset, distance) = self.profile_offset(point);
+        if distance == 0. {
+            return Vec3::up();
+        }
+        revolved_normal(point, &offset.scale(1. / distance))
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.half_height.min(self.bottom_radius.max(self.top_radius)) / 1_000.0
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        let r = self.bottom_radius.max(self.top_radius);
+        Some(Bounds::centered(Vec3::new(r, self.half_height, r)))
+    }
+}
+
+impl SDF for Ellipsoid {
+    fn distance(&self, point: &Vec3) -> f64 {
+        // there's no closed form for this, so this is the distance to the unit sphere in a
+        // space where the ellipsoid is a unit sphere, scaled down so it never overshoots.
+        let k = point.clone().scale_vec(&Vec3::new(1. / self.radii.x, 1. / self.radii.y, 1. / self.radii.z));
+        (k.norm() - 1.) * self.min_radius()
+    }
+
+    fn normal(&self, point: &Vec3) -> Vec3 {
+        let r2 = self.radii.clone().scale_vec(&self.radii);
+        let n = Vec3::new(point.x / r2.x, point.y / r2.y, point.z / r2.z);
+        if n.norm2() == 0. {
+            return Vec3::up();
+        }
+        n.normalize()
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.min_radius() / 1_000.0
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        Some(Bounds::centered(self.radii.clone()))
+    }
+}
+
+impl SDF for HexPrism {
+    fn distance(&self, point: &Vec3) -> f64 {
+        prism_distance(self.hexagon(point).0, point.y, self.half_height)
+    }
+
+    fn normal(&self, point: &Vec3) -> Vec3 {
+        let (flat, gradient) = self.hexagon(point);
+        prism_normal(flat, Vec3::new(gradient.x, 0., gradient.y), point.y, self.half_height)
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.radius.min(self.half_height) / 1_000.0
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        // the corners stick out further than the flat sides.
+        let r = self.radius * 2. / 3f64.sqrt();
+        Some(Bounds::centered(Vec3::new(r, self.half_height, r)))
+    }
+}
+
+impl SDF for Octahedron {
+    fn distance(&self, point: &Vec3) -> f64 {
+        self.nearest(point).0
+    }
+
+    fn normal(&self, point: &Vec3) -> Vec3 {
+        let (distance, nearest) = self.nearest(point);
+        if distance == 0. {
+            return Vec3::new(point.x.signum(), point.y.signum(), point.z.signum()).normalize();
+        }
+        (point - &nearest).scale(1. / distance)
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.size / 1_000.0
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        Some(Bounds::centered(Vec3::new(self.size, self.size, self.size)))
+    }
+}
+
+impl SDF for Plane {
+    fn distance(&self, point: &Vec3) -> f64 {
+        self.normal.dot(point)
+    }
+
+    fn epsilon(&self) -> f64 {
+        0.001
+    }
+}
+
+impl SDF for Disk {
+    fn distance(&self, point: &Vec3) -> f64 {
+        // this is really just the intersection of a sphere and an infinite plane
+        self.normal.dot(point).max(point.norm() - self.radius)
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.radius / 1_000.0
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        Some(Bounds::centered(Vec3::new(self.radius, self.radius, self.radius)))
+    }
+}
+
+impl SDF for EmptySDF {
+    fn distance(&self, _: &Vec3) -> f64 {
+        MAX_FLOAT
+    }
+
+    fn epsilon(&self) -> f64 {
+        1.
+    }
+}
+
+impl SDF for PolyFace {
+    fn distance(&self, point: &Vec3) -> f64 {
+        let (flat, height) = self.offsets(point);
+        let w = Vec2::new(self.outline.distance(&flat), height.abs() - self.thickness / 2.);
+        w.x.max(w.y).min(0.) + Vec2::new(w.x.max(0.), w.y.max(0.)).norm()
+    }
+
+    fn normal(&self, point: &Vec3) -> Vec3 {
+        let (flat, height) = self.offsets(point);
+        let w = Vec2::new(self.outline.distance(&flat), height.abs() - self.thickness / 2.);
+        let g = self.outline.gradient(&flat);
+        let side = self.frame.project_vec(&Vec3::new(g.x, g.y, 0.));
+        let face = self.frame.basis.axes.2.clone().scale(height.signum());
+        if w.x > 0. && w.y > 0. {
+            side.scale(w.x).add(w.y, &face).normalize()
+        } else if w.x > w.y {
+            side
+        } else {
+            face
+        }
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.outline.epsilon()
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        let back = self.frame.basis.axes.2.clone().scale(-self.thickness);
+        let front: Vec<Vec3> = self.outline.vertices().iter()
+            .map(|v| self.frame.project_point(&Vec3::new(v.x, v.y, 0.)))
+            .collect();
+        let corners: Vec<Vec3> = front.iter()
+            .map(|v| v + &back)
+            .chain(front.iter().cloned())
+            .collect();
+        Some(Bounds::around(&corners))
+    }
+}
+
+pub struct MatSDF {
+    sdf: Box<dyn SDF>,
+    mat: Material,
+}
+
+pub struct DynFuncSdf {
+    func: Box<dyn Fn(&Vec3) -> f64>,
+    epsilon: f64,
+}
+
+pub struct FuncSdf {
+    func: fn(&Vec3) -> f64,
+    epsilon: f64,
+}
+
+pub struct UnionSDF {
+    a: Box<dyn SDF>,
+    b: Box<dyn SDF>,
+}
+
+pub struct SmoothUnionSDF {
+    a: Box<dyn SDF>,
+    b: Box<dyn SDF>,
+    smooth: SmoothUnionType,
+}
+
+pub struct SmoothIntersectionSDF {
+    a: Box<dyn SDF>,
+    b: Box<dyn SDF>,
+    smooth: SmoothUnionType,
+}
+
+pub struct SmoothDifferenceSDF {
+    a: Box<dyn SDF>,
+    b: Box<dyn SDF>,
+    smooth: SmoothUnionType,
+}
+
+/// The blending kernel used by the smooth union, intersection and difference.
+pub enum SmoothUnionType {
+    /** exponential smoothing, default parameter = 32 */
+    Exp(f64),
+    /** polynomial smoothing, default parameter = 0.1 */
+    Poly(f64),
+    /** power smoothing, default parameter = 8 */
+    Pow(f64),
+    /** a 45° bevel of the given radius */
+    Chamfer(f64),
+    /** the given radius, divided into the given number of steps */
+    Stairs(f64, f64),
+}
+
+pub struct IntersectionSDF {
+    a: Box<dyn SDF>,
+    b: Box<dyn SDF>,
+}
+
+
+pub struct DifferenceSDF {
+    a: Box<dyn SDF>,
+    b: Box<dyn SDF>,
+}
+
+pub struct NegationSDF {
+    sdf: Box<dyn SDF>,
+}
+
+pub struct NegatedRefSDF<'a> {
+    sdf: &'a Box<dyn SDF>,
+}
+
+pub struct TranslatedSDF {
+    sdf: Box<dyn SDF>,
+    translation: Vec3,
+}
+
+pub struct ScaledSDF {
+    sdf: Box<dyn SDF>,
+    scale: f64,
+}
+
+pub struct RotatedSDF {
+    sdf: Box<dyn SDF>,
+    angle: f64,
+    axis: Vec3,
+}
+
+pub struct TransformedSDF {
+    sdf: Box<dyn SDF>,
+    func: Box<dyn Fn(&Vec3) -> Vec3>,
+    lipschitz: f64,
+}
+
+pub struct RepeatSDF {
+    sdf: Box<dyn SDF>,
+    spacing: Vec3,
+    count: Option<(usize, usize, usize)>,
+}
+
+pub struct PolarRepeatSDF {
+    sdf: Box<dyn SDF>,
+    axis: Vec3,
+    count: usize,
+    reference: Vec3,
+}
+
+pub struct TwistSDF {
+    sdf: Box<dyn SDF>,
+    rate: f64,
+    radius: f64,
+}
+
+pub struct BendSDF {
+    sdf: Box<dyn SDF>,
+    rate: f64,
+    radius: f64,
+}
+
+pub struct TaperSDF {
+    sdf: Box<dyn SDF>,
+    rate: f64,
+    height: f64,
+    radius: f64,
+}
+
+pub struct DisplacedSDF {
+    sdf: Box<dyn SDF>,
+    displacement: Box<dyn Displacement>,
+}
+
+pub struct RoundedSDF {
+    sdf: Box<dyn SDF>,
+    radius: f64,
+}
+
+pub struct ShellSDF {
+    sdf: Box<dyn SDF>,
+    thickness: f64,
+}
+
+pub struct ElongatedSDF {
+    sdf: Box<dyn SDF>,
+    half_length: Vec3,
+}
+
+pub struct MirrorSDF {
+    sdf: Box<dyn SDF>,
+    normal: Vec3,
+    offset: f64,
+}
+
+pub struct SymmetricSDF {
+    sdf: Box<dyn SDF>,
+    axes: (bool, bool, bool),
+}
+
+/// An offset added to the surface of a shape.
+pub trait Displacement {
+    fn displacement(&self, point: &Vec3) -> f64;
+
+    /// The most the displacement can change per unit of distance. Steps are shortened by this
+    /// much so the raymarcher doesn't overshoot the displaced surface.
+    fn lipschitz(&self) -> f64;
+}
+
+/// Lets boxed shapes be built on like any other, e.g. by the scene's modifier commands.
+impl SDF for Box<dyn SDF> {
+    fn distance(&self, point: &Vec3) -> f64 {
+        self.as_ref().distance(point)
+    }
+
+    fn normal(&self, point: &Vec3) -> Vec3 {
+        self.as_ref().normal(point)
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.as_ref().epsilon()
+    }
+
+    fn material(&self, point: &Vec3) -> Option<Material> {
+        self.as_ref().material(point)
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        self.as_ref().bounds()
+    }
+}
+
+impl SDF for MatSDF {
+    fn distance(&self, point: &Vec3) -> f64 {
+        self.sdf.distance(point)
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.sdf.epsilon()
+    }
+
+    fn material(&self, _: &Vec3) -> Option<Material> {
+        Some(self.mat.clone())
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        self.sdf.bounds()
+    }
+}
+
+impl FuncSdf {
+    pub fn new(func: fn(&Vec3) -> f64, epsilon: f64) -> Self {
+        FuncSdf {
+            func,
+            epsilon,
+        }
+    }
+}
+
+impl SDF for DynFuncSdf {
+    fn distance(&self, point: &Vec3) -> f64 {
+        (self.func)(point)
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.epsilon
+    }
+}
+
+impl SDF for UnionSDF {
+    fn distance(&self, point: &Vec3) -> f64 {
+        self.a.distance(point).min(self.b.distance(point))
+    }
+
+    fn epsilon(&self) -> f64 {
         self.a.epsilon().min(self.b.epsilon())
     }
 
@@ -459,6 +1357,10 @@ impl SDF for UnionSDF {
             self.b.material(p)
         }
     }
+
+    fn bounds(&self) -> Option<Bounds> {
+        Some(self.a.bounds()?.union(&self.b.bounds()?))
+    }
 }
 
 impl SDF for SmoothUnionSDF {
@@ -469,30 +1371,153 @@ impl SDF for SmoothUnionSDF {
     fn epsilon(&self) -> f64 {
         self.a.epsilon().min(self.b.epsilon()) / 10.
     }
+
+    fn material(&self, p: &Vec3) -> Option<Material> {
+        let (_, blend) = self.smooth.blend(self.a.distance(p), self.b.distance(p));
+        blend_materials(self.a.material(p), self.b.material(p), blend)
+    }
+}
+
+impl SDF for SmoothIntersectionSDF {
+    fn distance(&self, point: &Vec3) -> f64 {
+        self.smooth.blend_max(self.a.distance(point), self.b.distance(point)).0
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.a.epsilon().min(self.b.epsilon()) / 10.
+    }
+
+    fn material(&self, p: &Vec3) -> Option<Material> {
+        let (_, blend) = self.smooth.blend_max(self.a.distance(p), self.b.distance(p));
+        blend_materials(self.a.material(p), self.b.material(p), blend)
+    }
+}
+
+impl SDF for SmoothDifferenceSDF {
+    fn distance(&self, point: &Vec3) -> f64 {
+        self.smooth.blend_max(self.a.distance(point), -self.b.distance(point)).0
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.a.epsilon().min(self.b.epsilon()) / 10.
+    }
+
+    fn material(&self, p: &Vec3) -> Option<Material> {
+        let (_, blend) = self.smooth.blend_max(self.a.distance(p), -self.b.distance(p));
+        blend_materials(self.a.material(p), self.b.material(p), blend)
+    }
+}
+
+/// Mixes the materials of two blended shapes, falling back on whichever one has a material.
+fn blend_materials(a: Option<Material>, b: Option<Material>, blend: f64) -> Option<Material> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.lerp(blend, &b)),
+        (a, b) => a.or(b),
+    }
 }
 
 impl SmoothUnionType {
+    /// Reads a kernel from scene arguments: `exp|poly|pow|chamfer k`, or `stairs radius steps`.
+    pub fn parse(arguments: &[&str]) -> Result<Self, String> {
+        let number = |s: &str| s.parse::<f64>().map_err(|e| format!("bad smoothing argument {}: {}", s, e));
+        match arguments {
+            ["exp", k] => Ok(SmoothUnionType::Exp(number(k)?)),
+            ["poly", k] => Ok(SmoothUnionType::Poly(number(k)?)),
+            ["pow", k] => Ok(SmoothUnionType::Pow(number(k)?)),
+            ["chamfer", radius] => Ok(SmoothUnionType::Chamfer(number(radius)?)),
+            ["stairs", radius, steps] => Ok(SmoothUnionType::Stairs(number(radius)?, number(steps)?)),
+            _ => Err(format!("unknown smoothing: {:?}", arguments)),
+        }
+    }
+
     pub fn smooth(&self, a: f64, b: f64) -> f64 {
+        self.blend(a, b).0
+    }
+
+    /// Smoothly blends the two distances, returning the blended distance along with how much
+    /// of the result comes from `b` (0 being all `a`, and 1 being all `b`).
+    pub fn blend(&self, a: f64, b: f64) -> (f64, f64) {
+        if self.is_hard() {
+            return (a.min(b), if a < b { 0. } else { 1. });
+        }
         // https://iquilezles.org/www/articles/smin/smin.htm
+        // http://mercury.sexy/hg_sdf/
         match self {
             SmoothUnionType::Exp(k) => {
-                let res = (-k * a).exp2() + (-k * b).exp2();
-                -(res.log2() / k)
+                // shifting by the minimum keeps exp2 from overflowing.
+                let m = a.min(b);
+                let wa = (-k * (a - m)).exp2();
+                let wb = (-k * (b - m)).exp2();
+                (m - (wa + wb).log2() / k, wb / (wa + wb))
             }
             SmoothUnionType::Poly(k) => {
-                let k = 0.1;
-                let h = (k - (a - b).abs()).max(0.0) / k;
-                a.min(b) - h * h * k * (1.0 / 4.0)
+                let h = (0.5 + 0.5 * (b - a) / k).clamp(0., 1.);
+                (b + h * (a - b) - k * h * (1.0 - h), 1.0 - h)
             }
             SmoothUnionType::Pow(k) => {
+                if a <= 0. || b <= 0. {
+                    // only defined for positive distances, but it converges to min(a, b) as
+                    // either distance approaches 0, so this is still continuous.
+                    return (a.min(b), if a < b { 0. } else { 1. });
+                }
                 let a = a.powf(*k);
                 let b = b.powf(*k);
-                ((a * b) / (a + b)).powf(1.0 / k)
+                (((a * b) / (a + b)).powf(1.0 / k), a / (a + b))
+            }
+            SmoothUnionType::Chamfer(r) => {
+                let d = a.min(b).min((a - r + b) * 0.5f64.sqrt());
+                (d, linear_blend(a, b, *r))
+            }
+            SmoothUnionType::Stairs(r, n) => {
+                let s = r / n;
+                let u = b - r;
+                let d = a.min(b).min(0.5 * (u + a + ((u - a + s).rem_euclid(2. * s) - s).abs()));
+                (d, linear_blend(a, b, *r))
+            }
+        }
+    }
+
+    /// The smooth maximum, for intersections and differences, with the same blend factor as
+    /// `blend`. Power smoothing only works on positive distances, so it's done on the negated
+    /// distances, which are positive inside both shapes where the intersection's edge gets
+    /// rounded off. Everywhere else it's the hard maximum.
+    pub fn blend_max(&self, a: f64, b: f64) -> (f64, f64) {
+        if self.is_hard() {
+            return (a.max(b), if a > b { 0. } else { 1. });
+        }
+        match self {
+            SmoothUnionType::Pow(k) => {
+                if a >= 0. || b >= 0. {
+                    return (a.max(b), if a > b { 0. } else { 1. });
+                }
+                let (na, nb) = ((-a).powf(*k), (-b).powf(*k));
+                (-((na * nb) / (na + nb)).powf(1.0 / k), na / (na + nb))
             }
+            _ => {
+                let (d, blend) = self.blend(-a, -b);
+                (-d, blend)
+            }
+        }
+    }
+
+    /// Whether the kernel has no room to blend in, where the formulas would divide by zero, so
+    /// it's just the hard minimum or maximum.
+    fn is_hard(&self) -> bool {
+        match *self {
+            SmoothUnionType::Exp(k)
+            | SmoothUnionType::Poly(k)
+            | SmoothUnionType::Pow(k)
+            | SmoothUnionType::Chamfer(k) => k <= 0.,
+            SmoothUnionType::Stairs(r, n) => r <= 0. || n <= 0.,
         }
     }
 }
 
+/// Blend factor that goes linearly from a to b as the distances cross within `radius`.
+fn linear_blend(a: f64, b: f64, radius: f64) -> f64 {
+    (0.5 + 0.5 * (a - b) / radius).clamp(0., 1.)
+}
+
 impl SDF for IntersectionSDF {
     fn distance(&self, point: &Vec3) -> f64 {
         self.a.distance(point).max(self.b.distance(point))
@@ -509,6 +1534,13 @@ impl SDF for IntersectionSDF {
             self.b.material(p)
         }
     }
+
+    fn bounds(&self) -> Option<Bounds> {
+        match (self.a.bounds(), self.b.bounds()) {
+            (Some(a), Some(b)) => Some(a.intersection(&b)),
+            (a, b) => a.or(b),
+        }
+    }
 }
 
 impl SDF for DifferenceSDF {
@@ -523,6 +1555,10 @@ impl SDF for DifferenceSDF {
     fn material(&self, p: &Vec3) -> Option<Material> {
         self.a.material(p)
     }
+
+    fn bounds(&self) -> Option<Bounds> {
+        self.a.bounds()
+    }
 }
 
 impl SDF for NegationSDF {
@@ -563,7 +1599,11 @@ impl SDF for TranslatedSDF {
     }
 
     fn material(&self, p: &Vec3) -> Option<Material> {
-        self.sdf.material(p)
+        self.sdf.material(&(p - &self.translation))
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        Some(self.sdf.bounds()?.translate(&self.translation))
     }
 }
 
@@ -577,7 +1617,14 @@ impl SDF for ScaledSDF {
     }
 
     fn material(&self, p: &Vec3) -> Option<Material> {
-        self.sdf.material(p)
+        self.sdf.material(&p.clone().scale(1.0 / self.scale))
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        let corners: Vec<Vec3> = self.sdf.bounds()?.corners().into_iter()
+            .map(|c| c.scale(self.scale))
+            .collect();
+        Some(Bounds::around(&corners))
     }
 }
 
@@ -591,18 +1638,230 @@ impl SDF for RotatedSDF {
     }
 
     fn material(&self, p: &Vec3) -> Option<Material> {
-        self.sdf.material(p)
+        self.sdf.material(&p.clone().rotate(-self.angle, &self.axis))
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        let corners: Vec<Vec3> = self.sdf.bounds()?.corners().into_iter()
+            .map(|c| c.rotate(self.angle, &self.axis))
+            .collect();
+        Some(Bounds::around(&corners))
     }
 }
 
 impl SDF for TransformedSDF {
     fn distance(&self, point: &Vec3) -> f64 {
-        (self.func)(point, &self.sdf)
+        self.sdf.distance(&(self.func)(point)) / self.lipschitz
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.sdf.epsilon()
+    }
+
+    fn material(&self, p: &Vec3) -> Option<Material> {
+        self.sdf.material(&(self.func)(p))
+    }
+}
+
+impl SDF for RepeatSDF {
+    fn distance(&self, point: &Vec3) -> f64 {
+        self.nearest_copy(point).0
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.sdf.epsilon()
+    }
+
+    fn material(&self, p: &Vec3) -> Option<Material> {
+        self.sdf.material(&self.nearest_copy(p).1)
+    }
+}
+
+impl SDF for PolarRepeatSDF {
+    fn distance(&self, point: &Vec3) -> f64 {
+        self.nearest_copy(point).0
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.sdf.epsilon()
+    }
+
+    fn material(&self, p: &Vec3) -> Option<Material> {
+        self.sdf.material(&self.nearest_copy(p).1)
+    }
+}
+
+impl SDF for TwistSDF {
+    fn distance(&self, point: &Vec3) -> f64 {
+        self.sdf.distance(&self.untwist(point)) / self.lipschitz(point)
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.sdf.epsilon()
+    }
+
+    fn material(&self, p: &Vec3) -> Option<Material> {
+        self.sdf.material(&self.untwist(p))
+    }
+}
+
+impl SDF for BendSDF {
+    fn distance(&self, point: &Vec3) -> f64 {
+        self.sdf.distance(&self.unbend(point)) / self.lipschitz(point)
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.sdf.epsilon()
+    }
+
+    fn material(&self, p: &Vec3) -> Option<Material> {
+        self.sdf.material(&self.unbend(p))
+    }
+}
+
+impl SDF for TaperSDF {
+    fn distance(&self, point: &Vec3) -> f64 {
+        self.sdf.distance(&self.untaper(point)) / self.lipschitz(point)
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.sdf.epsilon()
+    }
+
+    fn material(&self, p: &Vec3) -> Option<Material> {
+        self.sdf.material(&self.untaper(p))
+    }
+}
+
+impl SDF for DisplacedSDF {
+    fn distance(&self, point: &Vec3) -> f64 {
+        (self.sdf.distance(point) + self.displacement.displacement(point)) / self.lipschitz()
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.sdf.epsilon()
+    }
+
+    fn material(&self, p: &Vec3) -> Option<Material> {
+        self.sdf.material(p)
+    }
+}
+
+impl SDF for RoundedSDF {
+    fn distance(&self, point: &Vec3) -> f64 {
+        self.sdf.distance(point) - self.radius
+    }
+
+    fn normal(&self, point: &Vec3) -> Vec3 {
+        self.sdf.normal(point)
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.sdf.epsilon()
+    }
+
+    fn material(&self, p: &Vec3) -> Option<Material> {
+        self.sdf.material(p)
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        let r = self.radius;
+        Some(self.sdf.bounds()?.expand(&Vec3::new(r, r, r)))
+    }
+}
+
+impl SDF for ShellSDF {
+    fn distance(&self, point: &Vec3) -> f64 {
+        let half = self.thickness / 2.;
+        (self.sdf.distance(point) + half).abs() - half
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.sdf.epsilon().min(self.thickness / 1_000.0)
+    }
+
+    fn material(&self, p: &Vec3) -> Option<Material> {
+        self.sdf.material(p)
+    }
+}
+
+impl ElongatedSDF {
+    fn shrink(&self, point: &Vec3) -> Vec3 {
+        let h = &self.half_length;
+        Vec3::new(
+            point.x - point.x.max(-h.x).min(h.x),
+            point.y - point.y.max(-h.y).min(h.y),
+            point.z - point.z.max(-h.z).min(h.z),
+        )
+    }
+}
+
+impl SDF for ElongatedSDF {
+    fn distance(&self, point: &Vec3) -> f64 {
+        self.sdf.distance(&self.shrink(point))
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.sdf.epsilon()
+    }
+
+    fn material(&self, p: &Vec3) -> Option<Material> {
+        self.sdf.material(&self.shrink(p))
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        Some(self.sdf.bounds()?.expand(&self.half_length))
+    }
+}
+
+impl MirrorSDF {
+    fn fold(&self, point: &Vec3) -> Vec3 {
+        let height = point * &self.normal - self.offset;
+        if height < 0. {
+            point.clone().add(-2. * height, &self.normal)
+        } else {
+            point.clone()
+        }
+    }
+}
+
+impl SDF for MirrorSDF {
+    fn distance(&self, point: &Vec3) -> f64 {
+        self.sdf.distance(&self.fold(point))
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.sdf.epsilon()
+    }
+
+    fn material(&self, p: &Vec3) -> Option<Material> {
+        self.sdf.material(&self.fold(p))
+    }
+}
+
+impl SymmetricSDF {
+    fn fold(&self, point: &Vec3) -> Vec3 {
+        let fold = |c: f64, axis: bool| if axis { c.abs() } else { c };
+        Vec3::new(
+            fold(point.x, self.axes.0),
+            fold(point.y, self.axes.1),
+            fold(point.z, self.axes.2),
+        )
+    }
+}
+
+impl SDF for SymmetricSDF {
+    fn distance(&self, point: &Vec3) -> f64 {
+        self.sdf.distance(&self.fold(point))
     }
 
     fn epsilon(&self) -> f64 {
         self.sdf.epsilon()
     }
+
+    fn material(&self, p: &Vec3) -> Option<Material> {
+        self.sdf.material(&self.fold(p))
+    }
 }
 
 impl SDF for FuncSdf {
@@ -627,7 +1886,8 @@ impl SDF for Vec3 {
 
 #[cfg(test)]
 mod tests {
-    use crate::linear::*;
+    use crate::mat::{Color, Material};
+    use crate::noise::Perlin;
     use crate::sdf::*;
 
     #[test]
@@ -640,4 +1900,248 @@ mod tests {
         assert_eq!(f.distance(&Vec3::new(2.0, 0.0, 0.0)).to_string(), 1.0.to_string());
         assert_eq!(f.distance(&Vec3::zero()).to_string(), (-1.0).to_string());
     }
+
+    #[test]
+    fn soft_shadows() {
+        let f = Sphere::new(1.0);
+        let toward = |x: f64| {
+            let ray = Ray::new(Vec3::new(x, -5., 0.), Vec3::up());
+            f.soft_shadow(&ray, 10., 8.)
+        };
+        assert_eq!(toward(0.), 0.);
+        assert_eq!(toward(5.), 1.);
+        // passing just outside the sphere is in the penumbra, and further out is brighter.
+        let (near, far) = (toward(1.05), toward(1.2));
+        assert!(0. < near && near < far && far < 1., "{} {}", near, far);
+    }
+
+    #[test]
+    fn primitives() {
+        let shapes: Vec<(&str, Box<dyn SDF>)> = vec![
+            ("sphere", Box::new(Sphere::new(1.))),
+            ("box", Box::new(Cuboid::new(Vec3::new(1., 2., 3.)))),
+            ("rounded box", Box::new(RoundedCuboid::new(Vec3::new(1., 2., 3.), 0.2))),
+            ("torus", Box::new(Torus::new(1., 0.25))),
+            ("capsule", Box::new(Capsule::new(Vec3::zero(), Vec3::new(1., 1., 0.), 0.5))),
+            ("cylinder", Box::new(Cylinder::new(0.5, 2.))),
+            ("cone", Box::new(Cone::new(2., 1., 0.25))),
+            ("ellipsoid", Box::new(Ellipsoid::new(Vec3::new(1., 0.5, 2.)))),
+            ("hex prism", Box::new(HexPrism::new(0.5, 1.))),
+            ("octahedron", Box::new(Octahedron::new(1.))),
+        ];
+        let points: Vec<Vec3> = (0..343)
+            .map(|i| Vec3::new((i % 7) as f64, ((i / 7) % 7) as f64, (i / 49) as f64))
+            .map(|p| p.scale(0.55).add(-1.6, &Vec3::new(1., 1., 1.)))
+            .collect();
+        for (name, f) in &shapes {
+            let bounds = f.bounds().unwrap();
+            // the ellipsoid is only a lower bound on the distance.
+            let exact = *name != "ellipsoid";
+            for p in &points {
+                let d = f.distance(p);
+                assert!(
+                    !exact || d.max(0.) >= bounds.distance(p) - 0.0001,
+                    "{} at {} is further than its bounds", name, p,
+                );
+                // compare the analytic normal to the default finite-difference one
+                let eps = 0.0001;
+                let numeric = Vec3::new(
+                    f.distance(&p.clone().add(eps, &Vec3::right())) - f.distance(&p.clone().add(-eps, &Vec3::right())),
+                    f.distance(&p.clone().add(eps, &Vec3::up())) - f.distance(&p.clone().add(-eps, &Vec3::up())),
+                    f.distance(&p.clone().add(eps, &Vec3::forward())) - f.distance(&p.clone().add(-eps, &Vec3::forward())),
+                ).scale(0.5 / eps);
+                // the gradient is shorter than 1 on creases, where the normal is ambiguous.
+                if exact && numeric.norm() > 0.99 {
+                    assert!(
+                        &f.normal(p) * &numeric > 0.99,
+                        "{} normal at {} is {}, should be {}", name, p, f.normal(p), numeric,
+                    );
+                }
+                for q in &points {
+                    assert!(
+                        (d - f.distance(q)).abs() <= p.dist(q) + 0.0001,
+                        "{} changes faster than distance between {} and {}", name, p, q,
+                    );
+                }
+            }
+        }
+        assert_eq!(Cuboid::new(Vec3::new(2., 2., 2.)).distance(&Vec3::new(2., 2., 1.)).to_string(), 2f64.sqrt().to_string());
+        assert_eq!(Torus::new(2., 0.5).distance(&Vec3::new(0., 1., 2.)).to_string(), 0.5.to_string());
+        assert_eq!(Cone::new(2., 1., 0.).distance(&Vec3::new(0., -2., 0.)).to_string(), 1.0.to_string());
+        assert_eq!(HexPrism::new(1., 2.).distance(&Vec3::new(0., 0., 3.)).to_string(), 2.0.to_string());
+        assert!((Octahedron::new(1.).distance(&Vec3::new(1., 1., 1.)) - 2. / 3f64.sqrt()).abs() < 0.0001);
+    }
+
+    #[test]
+    fn poly_faces() {
+        // an L shape in the xz plane facing up, with its first vertices nearly collinear.
+        let l_shape = vec![
+            Vec3::new(0.000001, 0., 2.),
+            Vec3::new(0., 0., 1.),
+            Vec3::new(0., 0., 0.),
+            Vec3::new(1., 0., 0.),
+            Vec3::new(1., 0., 1.),
+            Vec3::new(2., 0., 1.),
+            Vec3::new(2., 0., 2.),
+        ];
+        let slab = PolyFace::new(l_shape.clone(), 0.5).unwrap();
+        assert!((slab.distance(&Vec3::new(1.5, 1., 1.5)) - 1.).abs() < 0.0001);
+        assert!((slab.distance(&Vec3::new(0.5, -0.25, 1.5)) + 0.25).abs() < 0.0001);
+        // inside the notch of the L, where a convex hull would claim to be.
+        assert!((slab.distance(&Vec3::new(1.5, -0.25, 0.5)) - 0.5).abs() < 0.0001);
+        assert!(slab.normal(&Vec3::new(0.5, 2., 0.5)).dist(&Vec3::up()) < 0.0001);
+        assert!(slab.normal(&Vec3::new(0.5, -2., 0.5)).dist(&Vec3::down()) < 0.0001);
+
+        let sheet = PolyFace::new(l_shape, 0.).unwrap();
+        assert!((sheet.distance(&Vec3::new(0.5, -1., 0.5)) - 1.).abs() < 0.0001);
+        assert!(sheet.normal(&Vec3::new(0.5, -1., 0.5)).dist(&Vec3::down()) < 0.0001);
+
+        assert!(PolyFace::new(vec![Vec3::zero(), Vec3::up()], 0.1).is_err());
+        assert!(PolyFace::new(vec![Vec3::zero(), Vec3::up(), Vec3::up().scale(2.)], 0.1).is_err());
+    }
+
+    #[test]
+    fn repetition() {
+        let f = Sphere::new(0.5).repeat(Vec3::new(2., 0., 0.));
+        assert_eq!(f.distance(&Vec3::new(-4., 0., 0.)).to_string(), (-0.5).to_string());
+        assert_eq!(f.distance(&Vec3::new(1., 0., 0.)).to_string(), 0.5.to_string());
+        assert_eq!(f.distance(&Vec3::new(0., 2., 0.)).to_string(), 1.5.to_string());
+
+        let f = Sphere::new(0.5).repeat_limited(Vec3::new(2., 0., 0.), (3, 1, 1));
+        assert_eq!(f.distance(&Vec3::new(4., 0., 0.)).to_string(), (-0.5).to_string());
+        assert_eq!(f.distance(&Vec3::new(8., 0., 0.)).to_string(), 3.5.to_string());
+        assert_eq!(f.distance(&Vec3::new(-2., 0., 0.)).to_string(), 1.5.to_string());
+
+        let f = Sphere::new(0.5).translate(Vec3::new(2., 0., 0.)).repeat_polar(Vec3::up(), 4);
+        for p in &[Vec3::new(0., 0., 2.), Vec3::new(-2., 0., 0.), Vec3::new(0., 0., -2.)] {
+            assert!((f.distance(p) + 0.5).abs() < 0.0001, "no copy at {}", p);
+        }
+        assert!((f.distance(&Vec3::zero()) - 1.5).abs() < 0.0001);
+    }
+
+    #[test]
+    fn modifiers() {
+        let f = Sphere::new(1.).round(0.5);
+        assert_eq!(f.distance(&Vec3::new(2., 0., 0.)).to_string(), 0.5.to_string());
+
+        let f = Sphere::new(1.).shell(0.25);
+        assert_eq!(f.distance(&Vec3::zero()).to_string(), 0.75.to_string());
+        assert_eq!(f.distance(&Vec3::new(0., 0.875, 0.)).to_string(), (-0.125).to_string());
+        assert_eq!(f.distance(&Vec3::new(0., 2., 0.)).to_string(), 1.0.to_string());
+        assert!(std::panic::catch_unwind(|| Sphere::new(1.).shell(0.)).is_err());
+
+        let f = Sphere::new(1.).elongate(Vec3::new(2., 0., 0.));
+        assert_eq!(f.distance(&Vec3::new(1.5, 1., 0.)).to_string(), 0.0.to_string());
+        assert_eq!(f.distance(&Vec3::new(-4., 0., 0.)).to_string(), 1.0.to_string());
+    }
+
+    #[test]
+    fn symmetry() {
+        let shaded = |radius: f64, color: &str| Sphere::new(radius).shaded({
+            let mut m = Material::new();
+            m.diffuse = Color::from_hexstring(color);
+            m
+        });
+        let f = shaded(0.5, "#ff0000").translate(Vec3::new(2., 0., 0.))
+            .union(Box::new(shaded(0.5, "#0000ff").translate(Vec3::new(2., 3., 0.))))
+            .rotate(PI / 2., Vec3::forward())
+            .translate(Vec3::new(0., 0., 1.))
+            .mirror(Vec3::forward(), 0.);
+        let color_at = |p: Vec3| f.material(&p).unwrap().diffuse.to_string();
+        assert!((f.distance(&Vec3::new(0., 2., -1.)) + 0.5).abs() < 0.0001);
+        assert!((f.distance(&Vec3::new(-3., 2., -1.)) + 0.5).abs() < 0.0001);
+        assert_eq!(color_at(Vec3::new(0., 2., -1.)), "#ff0000");
+        assert_eq!(color_at(Vec3::new(-3., 2., -1.)), "#0000ff");
+
+        let f = Sphere::new(0.5).translate(Vec3::new(1., 1., 1.)).symmetric((true, false, true));
+        assert!((f.distance(&Vec3::new(-1., 1., -1.)) + 0.5).abs() < 0.0001);
+        assert!((f.distance(&Vec3::new(-1., -1., -1.)) - 1.5).abs() < 0.0001);
+    }
+
+    #[test]
+    fn smooth_kernels() {
+        let kernels = vec![
+            SmoothUnionType::Exp(32.),
+            SmoothUnionType::Poly(0.1),
+            SmoothUnionType::Pow(8.),
+            SmoothUnionType::Chamfer(0.1),
+            SmoothUnionType::Stairs(0.1, 3.),
+        ];
+        for kernel in &kernels {
+            for &(a, b) in &[(1., 2.), (-1., 0.5), (-0.5, -0.2), (0.03, 0.04), (-20., 20.)] {
+                let (d, blend) = kernel.blend(a, b);
+                assert!(!d.is_nan() && d <= a.min(b) + 0.0001, "{} is not a union of {}, {}", d, a, b);
+                assert!((0. ..=1.).contains(&blend));
+            }
+        }
+        // kernels with no room to blend in are hard, rather than dividing by zero.
+        for kernel in &[SmoothUnionType::Exp(0.), SmoothUnionType::Poly(0.), SmoothUnionType::Pow(0.),
+                        SmoothUnionType::Chamfer(0.), SmoothUnionType::Stairs(0.1, 0.)] {
+            assert_eq!(kernel.blend(0.5, 0.5).0, 0.5);
+            assert_eq!(kernel.blend(0.2, 0.7).0, 0.2);
+            assert_eq!(kernel.blend_max(-0.5, -0.5).0, -0.5);
+            assert_eq!(kernel.blend_max(-0.2, -0.7).0, -0.2);
+        }
+        // polynomial smoothing shouldn't ignore its parameter.
+        assert!(SmoothUnionType::Poly(0.5).smooth(0., 0.) < SmoothUnionType::Poly(0.1).smooth(0., 0.));
+
+        let shaded = |color: &str| Sphere::new(1.).shaded({
+            let mut m = Material::new();
+            m.diffuse = Color::from_hexstring(color);
+            m
+        });
+        let f = shaded("#ff0000")
+            .smooth_union(Box::new(shaded("#0000ff").translate(Vec3::new(1., 0., 0.))), None);
+        assert_eq!(f.material(&Vec3::new(0.5, 1., 0.)).unwrap().diffuse.to_string(), "#7f007f");
+
+        let f = Sphere::new(1.)
+            .smooth_difference(Box::new(Sphere::new(1.).translate(Vec3::new(1., 0., 0.))), None);
+        assert!(f.distance(&Vec3::new(-0.5, 0., 0.)) < 0.);
+        assert!(f.distance(&Vec3::new(0.5, 0., 0.)) > 0.);
+        let f = Sphere::new(1.)
+            .smooth_intersection(Box::new(Sphere::new(1.).translate(Vec3::new(1., 0., 0.))), None);
+        assert!(f.distance(&Vec3::new(0.5, 0., 0.)) < 0.);
+        assert!(f.distance(&Vec3::new(-0.5, 0., 0.)) > 0.);
+
+        // every kernel rounds off the edge where the spheres meet, just inside it.
+        let seam = Vec3::new(0.5, 0.84, 0.);
+        let hard = Sphere::new(1.)
+            .intersection(Box::new(Sphere::new(1.).translate(Vec3::new(1., 0., 0.))))
+            .distance(&seam);
+        assert!(hard < 0.);
+        for kernel in kernels {
+            let f = Sphere::new(1.)
+                .smooth_intersection(Box::new(Sphere::new(1.).translate(Vec3::new(1., 0., 0.))), Some(kernel));
+            assert!(f.distance(&seam) > hard + 1e-4, "{} vs {}", f.distance(&seam), hard);
+        }
+    }
+
+    #[test]
+    fn deformations_dont_overshoot() {
+        let offset = || Sphere::new(0.5).translate(Vec3::new(1., 0., 0.));
+        let shapes: Vec<(&str, Box<dyn SDF>)> = vec![
+            ("twist", Box::new(offset().twist(3., 1.5))),
+            ("bend", Box::new(offset().bend(2., 1.5))),
+            ("taper", Box::new(offset().taper(0.5, 1., 1.5))),
+            ("displace", Box::new(offset().displace(Perlin::new(1, 2.).displacement(0.2)))),
+            ("warp", Box::new(offset().transformed(
+                Box::new(|p| p + &Vec3::right().scale(0.1 * (p.y * PI * 5.).sin())),
+                shear_lipschitz(0.1 * PI * 5.),
+            ))),
+        ];
+        let points: Vec<Vec3> = (0..216)
+            .map(|i| Vec3::new((i % 6) as f64, ((i / 6) % 6) as f64, (i / 36) as f64))
+            .map(|p| p.scale(0.6).add(-1.5, &Vec3::new(1., 1., 1.)))
+            .collect();
+        for (name, f) in &shapes {
+            for a in &points {
+                for b in &points {
+                    assert!(
+                        (f.distance(a) - f.distance(b)).abs() <= a.dist(b) + 0.0001,
+                        "{} changes faster than distance between {} and {}", name, a, b,
+                    );
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,282 @@
+use crate::linear::*;
+use crate::sdf::SDF;
+
+/// A signed distance function in the plane, which can be turned into a 3d shape by extruding
+/// or revolving it.
+pub trait SDF2 {
+    fn distance(&self, point: &Vec2) -> f64;
+
+    /// Direction in which the distance increases fastest (the outward normal on the boundary).
+    fn gradient(&self, point: &Vec2) -> Vec2 {
+        let epsilon = self.epsilon();
+        Vec2::new(
+            self.distance(&Vec2::new(point.x + epsilon, point.y))
+                - self.distance(&Vec2::new(point.x - epsilon, point.y)),
+            self.distance(&Vec2::new(point.x, point.y + epsilon))
+                - self.distance(&Vec2::new(point.x, point.y - epsilon)),
+        ).normalize()
+    }
+
+    fn epsilon(&self) -> f64;
+
+    /// Extrudes the shape along the z axis, centered on the xy plane.
+    fn extrude(self, depth: f64) -> ExtrudedSDF where Self: Sized + 'static {
+        ExtrudedSDF::new(Box::new(self), depth)
+    }
+
+    /// Revolves the shape around the y axis, with the shape's x axis pointing away from the
+    /// axis of revolution and its origin `offset` units away from it.
+    fn revolve(self, offset: f64) -> RevolvedSDF where Self: Sized + 'static {
+        RevolvedSDF::new(Box::new(self), offset)
+    }
+}
+
+#[derive(Clone)]
+pub struct Circle {
+    radius: f64,
+}
+
+#[derive(Clone)]
+pub struct Rect {
+    half_size: Vec2,
+}
+
+#[derive(Clone)]
+pub struct Polygon {
+    vertices: Vec<Vec2>,
+    epsilon: f64,
+}
+
+pub struct ExtrudedSDF {
+    shape: Box<dyn SDF2>,
+    half_depth: f64,
+}
+
+pub struct RevolvedSDF {
+    shape: Box<dyn SDF2>,
+    offset: f64,
+}
+
+impl Circle {
+    pub fn new(radius: f64) -> Self {
+        Self { radius }
+    }
+}
+
+impl Rect {
+    pub fn new(width: f64, height: f64) -> Self {
+        Self { half_size: Vec2::new(width / 2., height / 2.) }
+    }
+}
+
+impl Polygon {
+    pub fn new(vertices: Vec<Vec2>) -> Self {
+        let epsilon = {
+            let mut eps: f64 = 1.;
+            for i in 0..vertices.len() {
+                let d = vertices[i].dist(&vertices[(i + 1) % vertices.len()]);
+                eps = eps.min(d / 1000.);
+            }
+            eps
+        };
+        Self { vertices, epsilon }
+    }
+
+    pub fn vertices(&self) -> &[Vec2] {
+        &self.vertices
+    }
+
+    /// Signed distance to the outline, along with the closest point on it.
+    fn nearest(&self, point: &Vec2) -> (f64, Vec2) {
+        if self.vertices.is_empty() {
+            return (f64::MAX, point.clone());
+        }
+        // https://iquilezles.org/www/articles/distfunctions2d/distfunctions2d.htm
+        let mut nearest = self.vertices[0].clone();
+        let mut sign = 1.;
+        for i in 0..self.vertices.len() {
+            let a = &self.vertices[i];
+            let b = &self.vertices[(i + 1) % self.vertices.len()];
+            let edge = b - a;
+            let w = point - a;
+            let s = if edge.norm2() > 0. {
+                (&w * &edge / edge.norm2()).clamp(0., 1.)
+            } else {
+                0.
+            };
+            let closest = a.clone().add(s, &edge);
+            if closest.dist(point) < nearest.dist(point) {
+                nearest = closest;
+            }
+            // flip the sign every time a ray from the point along +x crosses an edge.
+            let above_a = point.y >= a.y;
+            let below_b = point.y < b.y;
+            let left = Vec2::cross(&edge, &w) > 0.;
+            if (above_a && below_b && left) || (!above_a && !below_b && !left) {
+                sign = -sign;
+            }
+        }
+        (sign * nearest.dist(point), nearest)
+    }
+}
+
+impl ExtrudedSDF {
+    pub fn new(shape: Box<dyn SDF2>, depth: f64) -> Self {
+        Self { shape, half_depth: depth / 2. }
+    }
+}
+
+impl RevolvedSDF {
+    pub fn new(shape: Box<dyn SDF2>, offset: f64) -> Self {
+        Self { shape, offset }
+    }
+
+    fn profile_point(&self, point: &Vec3) -> Vec2 {
+        Vec2::new((point.x * point.x + point.z * point.z).sqrt() - self.offset, point.y)
+    }
+}
+
+impl SDF2 for Circle {
+    fn distance(&self, point: &Vec2) -> f64 {
+        point.norm() - self.radius
+    }
+
+    fn gradient(&self, point: &Vec2) -> Vec2 {
+        // every direction is straight out from the center, so just pick one.
+        if point.norm2() == 0. {
+            return Vec2::new(1., 0.);
+        }
+        point.clone().normalize()
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.radius / 1_000.0
+    }
+}
+
+impl SDF2 for Rect {
+    fn distance(&self, point: &Vec2) -> f64 {
+        let dx = point.x.abs() - self.half_size.x;
+        let dy = point.y.abs() - self.half_size.y;
+        Vec2::new(dx.max(0.), dy.max(0.)).norm() + dx.max(dy).min(0.)
+    }
+
+    fn gradient(&self, point: &Vec2) -> Vec2 {
+        let dx = point.x.abs() - self.half_size.x;
+        let dy = point.y.abs() - self.half_size.y;
+        let g = if dx > 0. || dy > 0. {
+            Vec2::new(dx.max(0.), dy.max(0.)).normalize()
+        } else if dx > dy {
+            Vec2::new(1., 0.)
+        } else {
+            Vec2::new(0., 1.)
+        };
+        Vec2::new(g.x * point.x.signum(), g.y * point.y.signum())
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.half_size.x.min(self.half_size.y) / 1_000.0
+    }
+}
+
+impl SDF2 for Polygon {
+    fn distance(&self, point: &Vec2) -> f64 {
+        self.nearest(point).0
+    }
+
+    fn gradient(&self, point: &Vec2) -> Vec2 {
+        let (distance, nearest) = self.nearest(point);
+        if distance == 0. {
+            return Vec2::new(1., 0.);
+        }
+        (point - &nearest).scale(1. / distance)
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.epsilon
+    }
+}
+
+impl SDF for ExtrudedSDF {
+    fn distance(&self, point: &Vec3) -> f64 {
+        let w = Vec2::new(
+            self.shape.distance(&Vec2::new(point.x, point.y)),
+            point.z.abs() - self.half_depth,
+        );
+        w.x.max(w.y).min(0.) + Vec2::new(w.x.max(0.), w.y.max(0.)).norm()
+    }
+
+    fn normal(&self, point: &Vec3) -> Vec3 {
+        let flat = Vec2::new(point.x, point.y);
+        let w = Vec2::new(self.shape.distance(&flat), point.z.abs() - self.half_depth);
+        let g = self.shape.gradient(&flat);
+        let side = Vec3::new(g.x, g.y, 0.);
+        let cap = Vec3::new(0., 0., point.z.signum());
+        if w.x > 0. && w.y > 0. {
+            side.scale(w.x).add(w.y, &cap).normalize()
+        } else if w.x > w.y {
+            side
+        } else {
+            cap
+        }
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.shape.epsilon()
+    }
+}
+
+impl SDF for RevolvedSDF {
+    fn distance(&self, point: &Vec3) -> f64 {
+        self.shape.distance(&self.profile_point(point))
+    }
+
+    fn normal(&self, point: &Vec3) -> Vec3 {
+        let g = self.shape.gradient(&self.profile_point(point));
+        let radial = Vec3::new(point.x, 0., point.z);
+        let radial = if radial.norm2() > 0. { radial.normalize() } else { Vec3::right() };
+        radial.scale(g.x).add(g.y, &Vec3::up())
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.shape.epsilon()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sdf::SDF;
+    use crate::sdf2::*;
+
+    #[test]
+    fn polygon_sdf() {
+        // an L shape, which isn't convex
+        let f = Polygon::new(vec![
+            Vec2::new(0., 0.),
+            Vec2::new(2., 0.),
+            Vec2::new(2., 1.),
+            Vec2::new(1., 1.),
+            Vec2::new(1., 2.),
+            Vec2::new(0., 2.),
+        ]);
+        assert_eq!(f.distance(&Vec2::new(0.5, 0.5)).to_string(), (-0.5).to_string());
+        assert_eq!(f.distance(&Vec2::new(1.5, 1.5)).to_string(), 0.5.to_string());
+        assert_eq!(f.distance(&Vec2::new(-1., 1.)).to_string(), 1.0.to_string());
+        assert_eq!(f.gradient(&Vec2::new(1.5, 1.25)).to_string(), Vec2::new(0., 1.).to_string());
+    }
+
+    #[test]
+    fn extrude_and_revolve() {
+        let slab = Rect::new(2., 2.).extrude(4.);
+        assert_eq!(slab.distance(&Vec3::new(0., 0., 3.)).to_string(), 1.0.to_string());
+        assert_eq!(slab.distance(&Vec3::new(2., 0., 0.)).to_string(), 1.0.to_string());
+        assert_eq!(slab.distance(&Vec3::new(0., 0., 1.5)).to_string(), (-0.5).to_string());
+        assert_eq!(slab.normal(&Vec3::new(0., 0., -3.)).to_string(), Vec3::backward().to_string());
+
+        let torus = Circle::new(0.5).revolve(2.);
+        assert_eq!(torus.distance(&Vec3::new(0., 0., -2.)).to_string(), (-0.5).to_string());
+        assert_eq!(torus.distance(&Vec3::zero()).to_string(), 1.5.to_string());
+        assert_eq!(torus.normal(&Vec3::new(2., 1., 0.)).to_string(), Vec3::up().to_string());
+        assert_eq!(Circle::new(1.).gradient(&Vec2::zero()).to_string(), Vec2::new(1., 0.).to_string());
+    }
+}
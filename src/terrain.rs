@@ -0,0 +1,257 @@
+
+use crate::linear::*;
+use crate::mat::Material;
+use crate::sdf::SDF;
+
+/// Ground at height h(x, z). The height only needs to be continuous, but the raymarcher has to
+/// take steps shrunk by the steepest slope anywhere, so very steep terrain renders slowly.
+pub struct Heightfield {
+    height: Box<dyn Fn(f64, f64) -> f64>,
+    /// largest rise over run anywhere on the terrain (the Lipschitz constant of the height).
+    slope: f64,
+    epsilon: f64,
+    bands: Vec<TerrainBand>,
+}
+
+/// A material for terrain at or above some altitude, and no steeper than some slope.
+#[derive(Clone)]
+pub struct TerrainBand {
+    pub altitude: f64,
+    pub max_slope: f64,
+    pub material: Material,
+}
+
+/// Samples of a grayscale image, with rows running along x and columns along z.
+struct Pixels {
+    width: usize,
+    depth: usize,
+    values: Vec<f64>,
+}
+
+impl Heightfield {
+    pub fn new(height: Box<dyn Fn(f64, f64) -> f64>, slope: f64, epsilon: f64) -> Self {
+        Self { height, slope: slope.abs(), epsilon, bands: vec![] }
+    }
+
+    /// Stretches a grayscale image with values from 0 to 1 over a `size.x` by `size.z` patch
+    /// centered on the origin, where white is `size.y` high. Heights in between pixels are
+    /// bilinearly interpolated, and the edge pixels extend out forever.
+    pub fn from_grayscale(width: usize, depth: usize, values: Vec<f64>, size: Vec3) -> Result<Self, String> {
+        if width < 2 || depth < 2 {
+            return Err(format!("heightfield needs at least 2x2 pixels, got {}x{}", width, depth));
+        }
+        let count = width.checked_mul(depth).ok_or_else(|| "heightfield too large".to_string())?;
+        if values.len() != count {
+            return Err(format!("{}x{} heightfield needs {} pixels, got {}", width, depth, count, values.len()));
+        }
+        let cell = (size.x / (width - 1) as f64, size.z / (depth - 1) as f64);
+        if cell.0.is_nan() || cell.1.is_nan() || cell.0 <= 0. || cell.1 <= 0. {
+            return Err("heightfield has no area".to_string());
+        }
+
+        let pixels = Pixels { width, depth, values };
+        // the bilinear slope along each axis is a mix of the differences between neighbors
+        // along that axis, so it's at most the biggest of them.
+        let mut rise = (0f64, 0f64);
+        for z in 0..depth {
+            for x in 0..width {
+                let h = pixels.get(x, z);
+                if x + 1 < width {
+                    rise.0 = rise.0.max((pixels.get(x + 1, z) - h).abs());
+                }
+                if z + 1 < depth {
+                    rise.1 = rise.1.max((pixels.get(x, z + 1) - h).abs());
+                }
+            }
+        }
+        let slope = Vec2::new(rise.0 * size.y / cell.0, rise.1 * size.y / cell.1).norm();
+        let epsilon = cell.0.min(cell.1) / 100.;
+
+        let origin = (-size.x / 2., -size.z / 2.);
+        let height = move |x: f64, z: f64| {
+            size.y * pixels.sample((x - origin.0) / cell.0, (z - origin.1) / cell.1)
+        };
+        Ok(Self::new(Box::new(height), slope, epsilon))
+    }
+
+    /// Parses a binary (P5) or plain (P2) PGM image into a heightfield, like `from_grayscale`.
+    pub fn from_pgm(bytes: &[u8], size: Vec3) -> Result<Self, String> {
+        let mut offset = 0;
+        let mut header = vec![];
+        while header.len() < 4 {
+            let rest = &bytes[offset..];
+            let skip = rest.iter().take_while(|b| b.is_ascii_whitespace()).count();
+            offset += skip;
+            if bytes.get(offset) == Some(&b'#') {
+                offset += bytes[offset..].iter().take_while(|&&b| b != b'\n').count();
+                continue;
+            }
+            let token: Vec<u8> = bytes[offset..].iter()
+                .take_while(|b| !b.is_ascii_whitespace())
+                .cloned()
+                .collect();
+            if token.is_empty() {
+                return Err("pgm header ended early".to_string());
+            }
+            offset += token.len();
+            header.push(String::from_utf8_lossy(&token).to_string());
+        }
+        let number = |s: &str| s.parse::<usize>().map_err(|_| format!("bad pgm header value {}", s));
+        let (width, depth, max) = (number(&header[1])?, number(&header[2])?, number(&header[3])?);
+        if max == 0 || max > 65535 {
+            return Err(format!("bad pgm max value {}", max));
+        }
+
+        let count = width.checked_mul(depth).ok_or_else(|| "heightfield too large".to_string())?;
+        let values: Vec<usize> = match header[0].as_str() {
+            "P5" => {
+                // exactly one whitespace byte separates the header from the pixels.
+                let data = &bytes[(offset + 1).min(bytes.len())..];
+                let stride = if max < 256 { 1 } else { 2 };
+                let size = count.checked_mul(stride).ok_or_else(|| "heightfield too large".to_string())?;
+                if data.len() < size {
+                    return Err(format!("pgm has {} bytes of pixels, expected {}", data.len(), size));
+                }
+                data.chunks(stride)
+                    .take(count)
+                    .map(|c| c.iter().fold(0, |v, &b| v * 256 + b as usize))
+                    .collect()
+            }
+            "P2" => {
+                let values: Result<Vec<usize>, String> = String::from_utf8_lossy(&bytes[offset..])
+                    .split_whitespace()
+                    .take(count)
+                    .map(number)
+                    .collect();
+                let values = values?;
+                if values.len() < count {
+                    return Err(format!("pgm has {} pixels, expected {}", values.len(), count));
+                }
+                values
+            }
+            format => return Err(format!("unsupported pgm format {}", format)),
+        };
+        let values = values.into_iter().map(|v| v as f64 / max as f64).collect();
+        Self::from_grayscale(width, depth, values, size)
+    }
+
+    /// Adds a material band. Bands added later take priority over earlier ones, so add the
+    /// broadest bands (e.g. bare rock) first.
+    pub fn band(mut self, altitude: f64, max_slope: f64, material: Material) -> Self {
+        self.bands.push(TerrainBand { altitude, max_slope, material });
+        self
+    }
+
+    /// The gradient of the height, as (dh/dx, dh/dz).
+    fn gradient(&self, x: f64, z: f64) -> Vec2 {
+        let e = self.epsilon;
+        Vec2::new(
+            (self.height)(x + e, z) - (self.height)(x - e, z),
+            (self.height)(x, z + e) - (self.height)(x, z - e),
+        ).scale(1. / (2. * e))
+    }
+}
+
+impl Pixels {
+    fn get(&self, x: usize, z: usize) -> f64 {
+        self.values[x + z * self.width]
+    }
+
+    /// Bilinearly interpolates at fractional pixel coordinates, clamped to the image.
+    fn sample(&self, x: f64, z: f64) -> f64 {
+        let x = x.max(0.).min((self.width - 1) as f64);
+        let z = z.max(0.).min((self.depth - 1) as f64);
+        let (x0, z0) = (x.floor() as usize, z.floor() as usize);
+        let (x1, z1) = ((x0 + 1).min(self.width - 1), (z0 + 1).min(self.depth - 1));
+        let (tx, tz) = (x - x0 as f64, z - z0 as f64);
+        let near = self.get(x0, z0) + (self.get(x1, z0) - self.get(x0, z0)) * tx;
+        let far = self.get(x0, z1) + (self.get(x1, z1) - self.get(x0, z1)) * tx;
+        near + (far - near) * tz
+    }
+}
+
+impl SDF for Heightfield {
+    fn distance(&self, point: &Vec3) -> f64 {
+        // the vertical gap overestimates the distance on slopes, but never by more than the
+        // steepest slope allows.
+        (point.y - (self.height)(point.x, point.z)) / (1. + self.slope * self.slope).sqrt()
+    }
+
+    fn normal(&self, point: &Vec3) -> Vec3 {
+        let g = self.gradient(point.x, point.z);
+        Vec3::new(-g.x, 1., -g.y).normalize()
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.epsilon
+    }
+
+    fn material(&self, point: &Vec3) -> Option<Material> {
+        let altitude = (self.height)(point.x, point.z);
+        let slope = self.gradient(point.x, point.z).norm();
+        self.bands.iter()
+            .rev()
+            .find(|band| altitude >= band.altitude && slope <= band.max_slope)
+            .map(|band| band.material.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mat::{Color, Material};
+    use crate::terrain::*;
+
+    #[test]
+    fn procedural_terrain() {
+        let hills = Heightfield::new(Box::new(|x: f64, z: f64| x.sin() + z.cos()), 2f64.sqrt(), 0.001);
+        assert_eq!(hills.distance(&Vec3::new(0., 1., 0.)).to_string(), 0.0.to_string());
+        // never overestimates the distance to the surface, even on the slopes.
+        for &(x, y, z) in &[(0.5, 3., 0.), (2., -1., 1.), (-1., 0.2, 0.7)] {
+            let d = hills.distance(&Vec3::new(x, y, z));
+            let nearest = (-200..=200)
+                .flat_map(|i| (-200..=200).map(move |j| (x + i as f64 * 0.02, z + j as f64 * 0.02)))
+                .map(|(sx, sz)| Vec3::new(sx, sx.sin() + sz.cos(), sz).dist(&Vec3::new(x, y, z)))
+                .fold(f64::INFINITY, f64::min);
+            assert!(d.abs() <= nearest + 0.001, "{} overshoots {} at {} {} {}", d, nearest, x, y, z);
+        }
+        assert!(hills.normal(&Vec3::new(0., 1., 0.)).dist(&Vec3::new(-1., 1., 0.).normalize()) < 0.001);
+    }
+
+    #[test]
+    fn pgm_terrain() {
+        // a ramp rising from 0 to 2 along x, from x = -1 to 1.
+        let pgm = b"P2\n# ramp\n3 2\n4\n0 2 4\n0 2 4\n";
+        let ramp = Heightfield::from_pgm(pgm, Vec3::new(2., 2., 1.)).unwrap();
+        assert_eq!(ramp.slope.to_string(), 1.0.to_string());
+        assert!((ramp.distance(&Vec3::new(0., 2., 0.)) - 0.5f64.sqrt()).abs() < 0.0001);
+        assert!((ramp.distance(&Vec3::new(5., 2., 9.)) - 0.).abs() < 0.0001);
+
+        let binary = [b"P5 3 2 255\n".to_vec(), vec![0, 127, 255, 0, 127, 255]].concat();
+        assert!(Heightfield::from_pgm(&binary, Vec3::new(2., 2., 1.)).is_ok());
+        assert!(Heightfield::from_pgm(b"P5 3 2 255\n\x00", Vec3::new(2., 2., 1.)).is_err());
+        assert!(Heightfield::from_pgm(b"P6 1 1 255\n\x00", Vec3::new(2., 2., 1.)).is_err());
+        let huge = format!("P5 {} {} 255\n\x00", usize::MAX, 3);
+        assert_eq!(
+            Heightfield::from_pgm(huge.as_bytes(), Vec3::new(2., 2., 1.)).err(),
+            Some("heightfield too large".to_string()),
+        );
+    }
+
+    #[test]
+    fn terrain_bands() {
+        let material = |hex: &str| {
+            let mut m = Material::new();
+            m.diffuse = Color::from_hexstring(hex);
+            m
+        };
+        // a gentle slope, then a steep one, then a plateau.
+        let hill = Heightfield::new(Box::new(|x: f64, _| (x * 0.1).max(x * 2.).min(4.)), 2., 0.001)
+            .band(f64::NEG_INFINITY, f64::INFINITY, material("#808080"))
+            .band(f64::NEG_INFINITY, 0.5, material("#00ff00"))
+            .band(1., 0.5, material("#ffffff"));
+        let color = |x: f64| hill.material(&Vec3::new(x, 0., 0.)).unwrap().diffuse.to_string();
+        assert_eq!(color(-1.), "#00ff00");
+        assert_eq!(color(1.), "#808080");
+        assert_eq!(color(3.), "#ffffff");
+    }
+}
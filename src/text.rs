@@ -0,0 +1,288 @@
+use ttf_parser::{Face, OutlineBuilder};
+
+use crate::linear::*;
+use crate::sdf2::SDF2;
+
+/// A TrueType or OpenType font, kept as raw bytes and parsed whenever text is laid out.
+pub struct Font {
+    data: Vec<u8>,
+}
+
+/// Closed outlines in the plane, filled by the nonzero winding rule like font glyphs are, so
+/// contours wound the other way cut holes.
+#[derive(Clone)]
+pub struct Outline {
+    contours: Vec<Vec<Vec2>>,
+    min: Vec2,
+    max: Vec2,
+}
+
+/// A line (or several) of text, with the first baseline along the x axis starting at the
+/// origin. Lines after the first go down the y axis.
+#[derive(Clone)]
+pub struct Text {
+    glyphs: Vec<Outline>,
+    epsilon: f64,
+}
+
+/// Collects glyph contours, flattening curves into line segments.
+struct OutlineCollector {
+    contours: Vec<Vec<Vec2>>,
+    current: Vec<Vec2>,
+    scale: f64,
+    offset: Vec2,
+}
+
+/// How many line segments each curve in a glyph is flattened into.
+const CURVE_SEGMENTS: usize = 8;
+
+impl Font {
+    pub fn new(data: Vec<u8>) -> Result<Self, String> {
+        Face::from_slice(&data, 0).map_err(|e| format!("couldn't parse font: {}", e))?;
+        Ok(Self { data })
+    }
+
+    /// Lays out the text with an em height of `size`. Newlines start new lines, and
+    /// characters the font doesn't have are skipped.
+    pub fn text(&self, text: &str, size: f64) -> Text {
+        let face = Face::from_slice(&self.data, 0).unwrap();
+        let scale = size / face.units_per_em() as f64;
+        let line_height = (face.ascender() - face.descender() + face.line_gap()) as f64 * scale;
+
+        let mut glyphs = vec![];
+        for (row, line) in text.lines().enumerate() {
+            let mut pen = Vec2::new(0., -(row as f64) * line_height);
+            for c in line.chars() {
+                let id = match face.glyph_index(c) {
+                    Some(id) => id,
+                    None => continue,
+                };
+                let mut collector = OutlineCollector::new(scale, pen.clone());
+                // whitespace has no outline, but it still has an advance.
+                if face.outline_glyph(id, &mut collector).is_some() {
+                    if let Some(outline) = collector.finish() {
+                        glyphs.push(outline);
+                    }
+                }
+                pen.x += face.glyph_hor_advance(id).unwrap_or(0) as f64 * scale;
+            }
+        }
+        Text { glyphs, epsilon: size / 1_000.0 }
+    }
+
+    /// Like `text`, but wraps the text to lines of at most `columns` characters first.
+    pub fn wrapped_text(&self, text: &str, size: f64, columns: usize) -> Text {
+        let lines: Vec<String> = text.lines()
+            .flat_map(|line| textwrap::wrap(line, columns))
+            .map(|line| line.to_string())
+            .collect();
+        self.text(&lines.join("\n"), size)
+    }
+}
+
+impl Outline {
+    /// Returns nothing if there are no contours with at least three points.
+    pub fn new(contours: Vec<Vec<Vec2>>) -> Option<Self> {
+        let contours: Vec<Vec<Vec2>> = contours.into_iter().filter(|c| c.len() >= 3).collect();
+        let points = contours.iter().flatten();
+        let min = points.clone().fold(Vec2::new(f64::INFINITY, f64::INFINITY), |m, p| {
+            Vec2::new(m.x.min(p.x), m.y.min(p.y))
+        });
+        let max = points.fold(Vec2::new(f64::NEG_INFINITY, f64::NEG_INFINITY), |m, p| {
+            Vec2::new(m.x.max(p.x), m.y.max(p.y))
+        });
+        if contours.is_empty() {
+            None
+        } else {
+            Some(Self { contours, min, max })
+        }
+    }
+
+    /// How far the point is from the bounding box, which the outline can't be any closer than.
+    fn bounds_distance(&self, point: &Vec2) -> f64 {
+        let dx = (self.min.x - point.x).max(point.x - self.max.x).max(0.);
+        let dy = (self.min.y - point.y).max(point.y - self.max.y).max(0.);
+        Vec2::new(dx, dy).norm()
+    }
+
+    /// Unsigned distance to the nearest edge, and the closest point on it.
+    fn nearest(&self, point: &Vec2) -> (f64, Vec2) {
+        let mut nearest = (f64::INFINITY, point.clone());
+        for contour in &self.contours {
+            for i in 0..contour.len() {
+                let a = &contour[i];
+                let b = &contour[(i + 1) % contour.len()];
+                let edge = b - a;
+                let s = if edge.norm2() > 0. {
+                    (&(point - a) * &edge / edge.norm2()).clamp(0., 1.)
+                } else {
+                    0.
+                };
+                let closest = a.clone().add(s, &edge);
+                let distance = closest.dist(point);
+                if distance < nearest.0 {
+                    nearest = (distance, closest);
+                }
+            }
+        }
+        nearest
+    }
+
+    fn winding_number(&self, point: &Vec2) -> i32 {
+        let mut winding = 0;
+        for contour in &self.contours {
+            for i in 0..contour.len() {
+                let a = &contour[i];
+                let b = &contour[(i + 1) % contour.len()];
+                let left = Vec2::cross(&(b - a), &(point - a));
+                if a.y <= point.y && b.y > point.y && left > 0. {
+                    winding += 1;
+                } else if a.y > point.y && b.y <= point.y && left < 0. {
+                    winding -= 1;
+                }
+            }
+        }
+        winding
+    }
+}
+
+impl OutlineCollector {
+    fn new(scale: f64, offset: Vec2) -> Self {
+        Self { contours: vec![], current: vec![], scale, offset }
+    }
+
+    fn point(&self, x: f32, y: f32) -> Vec2 {
+        Vec2::new(x as f64, y as f64).scale(self.scale).add(1., &self.offset)
+    }
+
+    fn last(&self) -> Vec2 {
+        self.current.last().cloned().unwrap_or_else(|| self.offset.clone())
+    }
+
+    fn finish(mut self) -> Option<Outline> {
+        self.close();
+        Outline::new(self.contours)
+    }
+}
+
+impl OutlineBuilder for OutlineCollector {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.close();
+        self.current.push(self.point(x, y));
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        self.current.push(self.point(x, y));
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let (p0, p1, p2) = (self.last(), self.point(x1, y1), self.point(x, y));
+        for i in 1..=CURVE_SEGMENTS {
+            let t = i as f64 / CURVE_SEGMENTS as f64;
+            let u = 1. - t;
+            self.current.push(p0.clone().scale(u * u).add(2. * u * t, &p1).add(t * t, &p2));
+        }
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        let (p0, p1, p2, p3) = (self.last(), self.point(x1, y1), self.point(x2, y2), self.point(x, y));
+        for i in 1..=CURVE_SEGMENTS {
+            let t = i as f64 / CURVE_SEGMENTS as f64;
+            let u = 1. - t;
+            self.current.push(p0.clone()
+                .scale(u * u * u)
+                .add(3. * u * u * t, &p1)
+                .add(3. * u * t * t, &p2)
+                .add(t * t * t, &p3));
+        }
+    }
+
+    fn close(&mut self) {
+        // contours come back around to their start, which we don't need twice.
+        if self.current.len() > 1 && self.current[0].dist(self.current.last().unwrap()) < 1e-9 {
+            self.current.pop();
+        }
+        if !self.current.is_empty() {
+            self.contours.push(std::mem::take(&mut self.current));
+        }
+    }
+}
+
+impl SDF2 for Outline {
+    fn distance(&self, point: &Vec2) -> f64 {
+        let distance = self.nearest(point).0;
+        if self.winding_number(point) != 0 {
+            -distance
+        } else {
+            distance
+        }
+    }
+
+    fn epsilon(&self) -> f64 {
+        (self.max.x - self.min.x).min(self.max.y - self.min.y) / 1_000.0
+    }
+}
+
+impl SDF2 for Text {
+    fn distance(&self, point: &Vec2) -> f64 {
+        let mut nearest = f64::INFINITY;
+        for glyph in &self.glyphs {
+            // glyphs can overlap (with tight kerning, or in script fonts), so we can only skip
+            // the ones whose bounds are further away than what we've found.
+            if glyph.bounds_distance(point) > nearest.max(0.) {
+                continue;
+            }
+            nearest = nearest.min(glyph.distance(point));
+        }
+        nearest
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.epsilon
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sdf::SDF;
+    use crate::text::*;
+
+    #[test]
+    fn outline_with_hole() {
+        // a square ring, with the hole wound clockwise.
+        let mut collector = OutlineCollector::new(1., Vec2::zero());
+        collector.move_to(0., 0.);
+        collector.line_to(4., 0.);
+        collector.line_to(4., 4.);
+        collector.line_to(0., 4.);
+        collector.line_to(0., 0.);
+        collector.move_to(1., 1.);
+        collector.line_to(1., 3.);
+        collector.line_to(3., 3.);
+        collector.quad_to(3., 2., 3., 1.);
+        let ring = collector.finish().unwrap();
+        assert_eq!(ring.contours.len(), 2);
+        assert_eq!(ring.contours[0].len(), 4);
+        assert_eq!(ring.distance(&Vec2::new(0.5, 2.)).to_string(), (-0.5).to_string());
+        assert_eq!(ring.distance(&Vec2::new(2., 2.)).to_string(), 1.0.to_string());
+        assert_eq!(ring.distance(&Vec2::new(6., 2.)).to_string(), 2.0.to_string());
+
+        let text = Text { glyphs: vec![ring.clone()], epsilon: 0.001 }.extrude(1.);
+        assert_eq!(text.distance(&Vec3::new(0.5, 2., 0.)).to_string(), (-0.5).to_string());
+        assert_eq!(text.distance(&Vec3::new(0.5, 2., 2.)).to_string(), 1.5.to_string());
+
+        // overlapping glyphs, where the point is deeper inside the second one.
+        let square = |x: f64| {
+            let mut collector = OutlineCollector::new(1., Vec2::new(x, 0.));
+            collector.move_to(0., 0.);
+            collector.line_to(4., 0.);
+            collector.line_to(4., 4.);
+            collector.line_to(0., 4.);
+            collector.finish().unwrap()
+        };
+        let text = Text { glyphs: vec![square(0.), square(3.)], epsilon: 0.001 };
+        assert!((text.distance(&Vec2::new(3.9, 2.)) + 0.9).abs() < 0.0001);
+
+        assert!(Font::new(b"not a font".to_vec()).is_err());
+    }
+}
@@ -1,10 +1,16 @@
+use std::collections::HashMap;
+
 use wasm_bindgen::JsCast;
 use wasm_bindgen::prelude::*;
 
-use crate::linear::{Frame, Ray, Vec3};
+use crate::buffer::AccumulationBuffer;
+use crate::light::Light;
+use crate::linear::{Frame, Vec3};
 use crate::mat;
 use crate::mat::{Color, Material, RefractionConstants};
-use crate::scene::{Light, OrthoView, PerspView, Scene, ViewTransform};
+use crate::path::Integrator;
+use crate::sample::{PixelProbe, Sampler};
+use crate::scene::{PerspView, Scene, ViewTransform};
 use crate::sdf;
 use crate::sdf::{SDF, SmoothUnionType};
 use crate::utils::current_time_millis;
@@ -16,9 +22,6 @@ extern "C" {
     // `log(..)`
     #[wasm_bindgen(js_namespace = console)]
     fn log(s: &str);
-
-    #[wasm_bindgen(js_namespace = Math)]
-    fn random() -> f64;
 }
 
 #[wasm_bindgen]
@@ -28,6 +31,19 @@ pub struct Viewport {
     index: usize,
     seed: u64,
     frame: u64,
+    /// The first pass's single samples, when sampling adaptively.
+    probes: Vec<PixelProbe>,
+    /// Which pixels the probes found on edges, when sampling adaptively. Only these get the
+    /// full set of samples on later passes, and the rest get one sample per pass.
+    edges: Vec<bool>,
+    /// Every sample so far, which each pass adds to.
+    buffer: AccumulationBuffer,
+    /// The scene file to render, if one's been given instead of the built-in scene.
+    scene_source: Option<String>,
+    /// Files the scene refers to, by the path it uses for them.
+    files: HashMap<String, Vec<u8>>,
+    /// The scene built from the above, which only changes when they do.
+    scene: Scene,
 }
 
 pub trait ViewportApi {
@@ -43,45 +59,84 @@ impl Viewport {
             .unwrap()
             .dyn_into::<web_sys::CanvasRenderingContext2d>()
             .unwrap();
+        let buffer = AccumulationBuffer::new(canvas.width() as usize, canvas.height() as usize);
+        let files = HashMap::new();
+        let scene = Self::build_scene(None, &files);
         Self {
             canvas,
             context,
             index: 0,
             seed: 0,
             frame: 0,
+            probes: vec![],
+            edges: vec![],
+            buffer,
+            scene_source: None,
+            files,
+            scene,
         }
     }
 
-    pub fn update(&mut self) {
-        if self.frame > 0 {
-            return;
+    /// Hands over a file the scene refers to (a mesh, voxel grid, heightfield or font), since
+    /// the browser can't read them itself. Starts over, in case the scene already uses it.
+    pub fn add_file(&mut self, path: &str, bytes: Vec<u8>) {
+        self.files.insert(path.to_string(), bytes);
+        self.reset();
+    }
+
+    /// Renders the scene file instead, starting over.
+    pub fn set_scene(&mut self, source: &str) {
+        self.scene_source = Some(source.to_string());
+        self.reset();
+    }
+
+    /// Starts the image over, rebuilding the scene. Call this whenever the camera or scene
+    /// changes.
+    pub fn reset(&mut self) {
+        self.scene = Self::build_scene(self.scene_source.as_deref(), &self.files);
+        self.scene.seed = self.seed;
+        self.index = 0;
+        self.frame = 0;
+        self.probes.clear();
+        self.edges.clear();
+        let (width, height) = (self.canvas.width() as usize, self.canvas.height() as usize);
+        if width == self.buffer.width() && height == self.buffer.height() {
+            self.buffer.reset();
+        } else {
+            self.buffer = AccumulationBuffer::new(width, height);
         }
+    }
 
+    pub fn update(&mut self) {
         //self.context.clear_rect(0., 0., self.canvas.width().into(), self.canvas.height().into());
         let width = self.canvas.width() as usize;
         let height = self.canvas.height() as usize;
+        if width != self.buffer.width() || height != self.buffer.height() {
+            self.reset();
+        }
 
-        let mut scene = self.get_scene();
+        if self.frame >= self.scene.passes() {
+            return; // more samples wouldn't make it look any better.
+        }
 
         if self.frame == 0 && self.index == 0 {
-            scene.debugging = true;
-            if let Some(c) = scene.raycast_pixel((width / 2, height / 2), width, height) {
+            self.scene.debugging = true;
+            if let Some(c) = self.scene.raycast_pixel((width / 2, height / 2), width, height, self.frame) {
                 log(&format!("debugged pixel color: {}", c));
             }
-            scene.debugging = false;
+            self.scene.debugging = false;
         }
 
         let time_budget_millis = 100.;
         let start_time_millis = current_time_millis();
 
         while current_time_millis() - start_time_millis < time_budget_millis {
-            self.render_next_point(&scene);
+            self.render_next_point();
         }
-
-        self.seed += 29;
     }
 
-    fn render_next_point(&mut self, scene: &Scene) {
+    fn render_next_point(&mut self) {
+        let scene = &self.scene;
         let width = self.canvas.width() as usize;
         let height = self.canvas.height() as usize;
 
@@ -91,30 +146,49 @@ impl Viewport {
         let x = x as f64;
         let y = y as f64;
 
-        let black = Color::black().to_string().into();
-
-        if let Some(color) = scene.raycast_pixel(pixel, width, height) {
-            let color = &color;
-            self.context.set_fill_style(&color.into());
-            self.context.fill_rect(x, y, 1., 1.);
-
-            if self.frame == 0 {
-                // log(&format!("eye: {}", &hit.ray));
-                // log(&format!("hit: {:#?}", &hit.distance));
-            }
+        let edge = self.edges.get(self.index).copied();
+        let color = if self.frame == 0 && scene.sampler.adaptive.is_some() {
+            let probe = scene.probe_pixel(pixel, width, height, self.frame);
+            let color = probe.color.clone();
+            self.probes.push(probe);
+            color
+        } else if edge == Some(false) {
+            scene.sample_pixel(pixel, width, height, self.frame)
         } else {
-            self.context.set_fill_style(&black);
-            self.context.fill_rect(x, y, 1., 1.);
+            scene.raycast_pixel(pixel, width, height, self.frame)
+        };
+        if self.frame == 1 && edge == Some(true) {
+            // the probe was only a guess, so it shouldn't count towards the average.
+            self.buffer.set(self.index, color);
+        } else {
+            self.buffer.add(self.index, color);
         }
+        self.fill_pixel(x, y, self.buffer.average(self.index));
 
         self.index = (self.index + 1) % (width * height);
         if self.index == 0 {
             self.frame += 1;
+            if !self.probes.is_empty() {
+                // the single samples are in, so we know where the edges are.
+                self.edges = vec![false; self.probes.len()];
+                for index in scene.sampler.refinements(&self.probes, width) {
+                    self.edges[index] = true;
+                }
+                self.probes.clear();
+            }
         }
     }
 
+    fn fill_pixel(&self, x: f64, y: f64, color: Option<Color>) {
+        let color = color.unwrap_or_else(Color::black);
+        self.context.set_fill_style(&(&color).into());
+        self.context.fill_rect(x, y, 1., 1.);
+    }
 
-    fn get_scene(&self) -> Scene {
+    fn build_scene(source: Option<&str>, files: &HashMap<String, Vec<u8>>) -> Scene {
+        if let Some(source) = source {
+            return Scene::parse_with_files(source.lines(), files);
+        }
         if false {
             let scene = "
 # four reflective spheres
@@ -170,9 +244,10 @@ write c5.png
                 m
             });
         let b = sdf::Sphere::new(1.)
-            .transformed(Box::new(|point, sdf| {
-                sdf.distance(&(point + &Vec3::right().scale(0.1 * (point.y * PI * 5.).sin())))
-            }))
+            .transformed(
+                Box::new(|point| point + &Vec3::right().scale(0.1 * (point.y * PI * 5.).sin())),
+                sdf::shear_lipschitz(0.1 * PI * 5.),
+            )
             .translate(Vec3::new(-3., 3., 7.))
             .shaded({
                 let mut m = Material::new();
@@ -299,12 +374,17 @@ write c5.png
             // }),
             far_plane: 1_000.,
             debugging: false,
+            seed: 0,
+            sampler: Sampler::new(),
+            integrator: Integrator::Whitted,
+            penumbra: None,
+            occlusion: None,
         }
     }
 }
 
 impl ViewportApi for Viewport {
-    fn handle_key_down(&mut self, key: &str) {
+    fn handle_key_down(&mut self, _key: &str) {
         // TODO
     }
 }
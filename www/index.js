@@ -2,6 +2,32 @@ import * as wasm from "gwendr";
 
 const program = wasm.setup();
 
+// commands whose first argument is a file, which has to be fetched for the scene.
+const FILE_COMMANDS = ['mesh', 'voxels', 'heightfield', 'text'];
+
+const fetchBytes = url => fetch(url)
+  .then(response => response.arrayBuffer())
+  .then(buffer => new Uint8Array(buffer));
+
+// `?scene=path/to/scene.txt` renders that scene file instead of the built-in one. Files it
+// refers to are fetched relative to it.
+const sceneUrl = new URLSearchParams(window.location.search).get('scene');
+if (sceneUrl) {
+  fetch(sceneUrl)
+    .then(response => response.text())
+    .then(text => {
+      const paths = text.split('\n')
+        .map(line => line.trim().split(/\s+/))
+        .filter(words => FILE_COMMANDS.includes(words[0]) && words.length > 1)
+        .map(words => words[1]);
+      const files = [...new Set(paths)].map(path =>
+        fetchBytes(new URL(path, new URL(sceneUrl, window.location.href)))
+          .then(bytes => program.add_file(path, bytes)));
+      return Promise.all(files).then(() => program.set_scene(text));
+    })
+    .catch(e => console.error(`Error loading scene ${sceneUrl}:`, e));
+}
+
 const mainLoop = () => {
   program.update();
   requestAnimationFrame(mainLoop);